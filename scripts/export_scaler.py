@@ -1,57 +1,148 @@
-import pandas as pd
+import json
+import sys
+
 import numpy as np
-from tensorflow.keras.models import Sequential
-from tensorflow.keras.layers import LSTM, Dense
-from sklearn.metrics import mean_squared_error
-from sklearn.preprocessing import RobustScaler
-from tqdm import tqdm
-import pickle
-from sklearn.metrics import r2_score
-import csv
-
-def load_dataset(train_file):
-    train_df = pd.read_csv(train_file)
-    train_dataset_X, train_dataset_Y = [], []
-    for group in tqdm(train_df.groupby("window_id")):
-        data = group[1].values
-        train_dataset_X.append(data[:, 1:-1])  # All rows, all but the last column
-        train_dataset_Y.append(data[:, -1])  # Last row, last column
-
-    # Reshape to the format [samples, timesteps, features]
-    train_dataset_X = np.array(train_dataset_X).reshape(-1, 180, train_dataset_X[0].shape[1])
-
-    # Reshape Y to be [samples, 1]
-    train_dataset_Y = np.array(train_dataset_Y).reshape(-1, 180, 1)
-
-    return (train_dataset_X, train_dataset_Y)
-
-
-def train_and_test_model(train_file, test_file, model_number):
-    print("Loading datasets...")
-    # Load datasets
-    #print(count_column_values(train_file, 'window_id'))
-    (trainX, trainY) = load_dataset(train_file)
-    
-    print(f'trainX shape: {trainX.shape}, trainY shape: {trainY.shape}')  # print shapes
-    print("Scaling data")
-    
-
-    #instantiate the RobustScaler object for X
-    scaler_X = RobustScaler()
-    # Reshape, fit and transform the training and test input data
-    trainX = scaler_X.fit_transform(trainX.reshape(-1, trainX.shape[-1])).reshape(trainX.shape)
-
-    # Save the scaler object to a file
-    scaler_file = f"scaler_{model_number}.pkl"
-    with open(scaler_file, 'wb') as file:
-        pickle.dump(scaler_X, file)
-    print(f"Scaler saved as {scaler_file}")
 
 
+def load_scalers(path):
+    """Scalers persisted by the preprocessor as scalers.json."""
+    with open(path) as f:
+        return json.load(f)
+
+
+def split_scalers(dataset_dir, split):
+    """Scalers the data of a split (numbered from 1) was scaled with, as named in splits.json."""
+    with open(f"{dataset_dir}/splits.json") as f:
+        splits = json.load(f)
+    entry = next(s for s in splits if s["split"] == split)
+    return load_scalers(f"{dataset_dir}/{entry['scalers']}")
+
+
+def steps(scalers, field, group="sensor", location=None):
+    """Fitted steps of a field, in the order they are applied. Rooms without
+    their own sensor scalers use the ones fitted on all rooms."""
+    if group == "sensor" and location in scalers.get("sensor_per_location", {}):
+        return scalers["sensor_per_location"][location].get(field, [])
+    return scalers.get(group, {}).get(field, [])
+
+
+def _quantile_references(quantiles):
+    return np.arange(len(quantiles)) / (max(len(quantiles), 2) - 1)
+
+
+def _yeo_johnson(x, lmbda):
+    positive = x >= 0
+    out = np.empty_like(x)
+    if abs(lmbda) < np.finfo(float).eps:
+        out[positive] = np.log1p(x[positive])
+    else:
+        out[positive] = (np.power(x[positive] + 1, lmbda) - 1) / lmbda
+    if abs(lmbda - 2) < np.finfo(float).eps:
+        out[~positive] = -np.log1p(-x[~positive])
+    else:
+        out[~positive] = -(np.power(-x[~positive] + 1, 2 - lmbda) - 1) / (2 - lmbda)
+    return out
+
+
+def _yeo_johnson_inverse(x, lmbda):
+    positive = x >= 0
+    out = np.empty_like(x)
+    if abs(lmbda) < np.finfo(float).eps:
+        out[positive] = np.expm1(x[positive])
+    else:
+        out[positive] = np.power(x[positive] * lmbda + 1, 1 / lmbda) - 1
+    if abs(lmbda - 2) < np.finfo(float).eps:
+        out[~positive] = -np.expm1(-x[~positive])
+    else:
+        out[~positive] = 1 - np.power(-(2 - lmbda) * x[~positive] + 1, 1 / (2 - lmbda))
+    return out
+
+
+def _apply(step, x):
+    kind = step["kind"]
+    if kind == "robust":
+        return (x - step["center"]) / step["scale"]
+    if kind == "standard":
+        return (x - step["mean"]) / step["std_dev"]
+    if kind == "min_max":
+        return (x - step["min"]) / step["range"]
+    if kind == "max_abs":
+        return x / step["max_abs"]
+    if kind == "quantile":
+        q = np.asarray(step["quantiles"], dtype=float)
+        if len(q) == 0:
+            return x
+        references = _quantile_references(q)
+        # sklearn's QuantileTransformer: average of interpolating upwards and downwards
+        forward = np.interp(x, q, references)
+        backward = -np.interp(-x, -q[::-1], -references[::-1])
+        return (forward + backward) / 2
+    if kind == "yeo_johnson":
+        return _yeo_johnson(x, step["lambda"])
+    if kind == "box_cox":
+        lmbda = step["lambda"]
+        return np.log(x) if abs(lmbda) < np.finfo(float).eps else (np.power(x, lmbda) - 1) / lmbda
+    if kind == "log1p":
+        return np.log1p(x)
+    raise ValueError(f"Unknown scaler kind {kind}")
+
+
+def _invert(step, x):
+    kind = step["kind"]
+    if kind == "robust":
+        return x * step["scale"] + step["center"]
+    if kind == "standard":
+        return x * step["std_dev"] + step["mean"]
+    if kind == "min_max":
+        return x * step["range"] + step["min"]
+    if kind == "max_abs":
+        return x * step["max_abs"]
+    if kind == "quantile":
+        q = np.asarray(step["quantiles"], dtype=float)
+        if len(q) == 0:
+            return x
+        return np.interp(np.clip(x, 0, 1) * (len(q) - 1), np.arange(len(q)), q)
+    if kind == "yeo_johnson":
+        return _yeo_johnson_inverse(x, step["lambda"])
+    if kind == "box_cox":
+        lmbda = step["lambda"]
+        return np.exp(x) if abs(lmbda) < np.finfo(float).eps else np.power(x * lmbda + 1, 1 / lmbda)
+    if kind == "log1p":
+        return np.expm1(x)
+    raise ValueError(f"Unknown scaler kind {kind}")
+
+
+def transform(field_steps, values):
+    """Scales raw values the way the preprocessor did."""
+    x = np.asarray(values, dtype=float)
+    for step in field_steps:
+        x = _apply(step, x)
+    return x
+
+
+def inverse_transform(field_steps, values):
+    """Maps scaled values, e.g. predictions, back to their original units."""
+    x = np.asarray(values, dtype=float)
+    for step in reversed(field_steps):
+        x = _invert(step, x)
+    return x
+
+
+def describe(scalers):
+    for group in ["sensor", "weather", "derived"]:
+        for field, field_steps in scalers.get(group, {}).items():
+            print(f"  {group} {field}: {field_steps}")
+    for location, fields in scalers.get("sensor_per_location", {}).items():
+        for field, field_steps in fields.items():
+            print(f"  sensor {field} ({location}): {field_steps}")
+
 
 if __name__ == "__main__":
-    for i in range(1, 11):
-        print(f"Processing fold {i}...")
-        train_file = f"./fold_{i}/train.csv"
-        test_file = f"./fold_{i}/test.csv"
-        train_and_test_model(train_file, test_file, i)
+    # The features in the fold files are already scaled with these scalers,
+    # nothing is fitted here.
+    dataset_dir = sys.argv[1] if len(sys.argv) > 1 else "."
+    with open(f"{dataset_dir}/splits.json") as f:
+        splits = json.load(f)
+    for split in splits:
+        print(f"Split {split['split']}: {split['scalers']}")
+        describe(load_scalers(f"{dataset_dir}/{split['scalers']}"))
@@ -3,15 +3,20 @@ import numpy as np
 from tensorflow.keras.models import Sequential
 from tensorflow.keras.layers import LSTM, Dense
 from sklearn.metrics import mean_squared_error
-from sklearn.preprocessing import RobustScaler
 from tqdm import tqdm
 import pickle
 from sklearn.metrics import r2_score
 import csv
+import json
+import sys
 
-def load_dataset(train_file, test_file):
-    train_df = pd.read_csv(train_file)
-    test_df = pd.read_csv(test_file)
+def read_folds(fold_files):
+    return pd.concat([pd.read_csv(f) for f in fold_files], ignore_index=True)
+
+
+def load_dataset(train_files, test_files):
+    train_df = read_folds(train_files)
+    test_df = read_folds(test_files)
 
     train_dataset_X, train_dataset_Y, test_dataset_X, test_dataset_Y = [], [], [], []
     for group in tqdm(train_df.groupby("window_id")):
@@ -42,22 +47,16 @@ def create_model(input_shape):
     return model
 
 
-def train_and_test_model(train_file, test_file, model_number):
+def train_and_test_model(train_files, test_files, model_number):
     print("Loading datasets...")
     # Load datasets
     #print(count_column_values(train_file, 'window_id'))
-    (trainX, trainY), (testX, testY) = load_dataset(train_file, test_file)
+    (trainX, trainY), (testX, testY) = load_dataset(train_files, test_files)
     
     print(f'trainX shape: {trainX.shape}, trainY shape: {trainY.shape}')  # print shapes
     print(f'testX shape: {testX.shape}, testY shape: {testY.shape}')  # print shapes
-    print("Scaling data")
-    
-
-    #instantiate the RobustScaler object for X
-    scaler_X = RobustScaler()
-    # Reshape, fit and transform the training and test input data
-    trainX = scaler_X.fit_transform(trainX.reshape(-1, trainX.shape[-1])).reshape(trainX.shape)
-    testX = scaler_X.transform(testX.reshape(-1, testX.shape[-1])).reshape(testX.shape)
+    # The features are already scaled by the preprocessor, with the scalers
+    # named in splits.json (see export_scaler.py)
 
     # If your output data is continuous and needs scaling
     print("Creating model...")
@@ -89,8 +88,13 @@ def train_and_test_model(train_file, test_file, model_number):
     model.save(f"./model_2_f{model_number}.h5")
 
 if __name__ == "__main__":
-    for i in range(1, 11):
+    # out/<name> or out/<provenance hash> as written by the preprocessor
+    dataset_dir = sys.argv[1] if len(sys.argv) > 1 else "."
+    with open(f"{dataset_dir}/splits.json") as f:
+        splits = json.load(f)
+    for split in splits:
+        i = split["split"]
         print(f"Processing fold {i}...")
-        train_file = f"./fold_{i}/train.csv"
-        test_file = f"./fold_{i}/test.csv"
-        train_and_test_model(train_file, test_file, i)
+        train_files = [f"{dataset_dir}/{name}" for name in split["train"]]
+        test_files = [f"{dataset_dir}/{name}" for name in split["test"]]
+        train_and_test_model(train_files, test_files, i)
@@ -3,11 +3,11 @@ import numpy as np
 from tensorflow.keras.models import Sequential
 from tensorflow.keras.layers import LSTM, Dense
 from sklearn.metrics import mean_squared_error
-from sklearn.preprocessing import RobustScaler
 from tqdm import tqdm
 import pickle
 from sklearn.metrics import r2_score
 import csv
+import sys
 from tensorflow import keras
 
 
@@ -34,13 +34,8 @@ def train_and_test_model(test_file):
     # Load datasets
     #print(count_column_values(train_file, 'window_id'))
     (testX, testY) = load_dataset(test_file)
-
-
-    # Reshape, fit and transform the training and test input data
-    scaler_file = 'scaler_1.pkl'  # Replace 'model_number' with the actual model number you used while saving
-    with open(scaler_file, 'rb') as file:
-        scaler_X = pickle.load(file)
-    testX = scaler_X.transform(testX.reshape(-1, testX.shape[-1])).reshape(testX.shape)
+    # The features are already scaled by the preprocessor, with the scalers
+    # named in splits.json (see export_scaler.py)
 
     print("Creating model...")
     # Create and fit the LSTM network
@@ -65,5 +60,7 @@ def train_and_test_model(test_file):
 
 
 if __name__ == "__main__":
-    test_file = f"./fold_1/test.csv"
+    # out/<name> or out/<provenance hash> as written by the preprocessor
+    dataset_dir = sys.argv[1] if len(sys.argv) > 1 else "."
+    test_file = f"{dataset_dir}/fold_1.csv"
     train_and_test_model(test_file)
@@ -3,15 +3,20 @@ import numpy as np
 from sklearn.metrics import mean_absolute_error, mean_squared_error, r2_score
 import matplotlib.pyplot as plt
 import pickle
+import sys
+
+# out/<name> or out/<provenance hash> as written by the preprocessor
+dataset_dir = sys.argv[1] if len(sys.argv) > 1 else "."
 
 # Load data
-data = pd.read_csv('../out/fold_1/test.csv')
+data = pd.read_csv(f'{dataset_dir}/fold_1.csv')
 
 # Ensure the data is numeric
 for column in data.columns:
     data[column] = pd.to_numeric(data[column], errors='coerce')
 
-with open('../out/predictions_1.pkl', 'rb') as f:
+# written by test_lstm.py
+with open('predictions_1.pkl', 'rb') as f:
     predictions = pickle.load(f)
 
 
@@ -0,0 +1,232 @@
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+/// What kind of day a date is, from the calendar file, the Slovenian public
+/// holidays or the day of the week, in that order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DayType {
+    SchoolDay,
+    Weekend,
+    PublicHoliday,
+    SchoolBreak,
+    ExamDay,
+    /// Anything else the calendar marks, e.g. sports or culture days.
+    SpecialDay,
+}
+
+impl DayType {
+    /// Value of the `day_type` column.
+    pub fn code(&self) -> f32 {
+        match self {
+            DayType::SchoolDay => 0.,
+            DayType::Weekend => 1.,
+            DayType::PublicHoliday => 2.,
+            DayType::SchoolBreak => 3.,
+            DayType::ExamDay => 4.,
+            DayType::SpecialDay => 5.,
+        }
+    }
+
+    /// Day type named in an iCal event, either by its snake case name in
+    /// `CATEGORIES` or by a keyword (English or Slovene) in `SUMMARY`.
+    fn from_event(categories: &str, summary: &str) -> DayType {
+        if let Ok(day_type) = serde_json::from_value(serde_json::Value::String(categories.trim().to_lowercase())) {
+            return day_type;
+        }
+        let summary = summary.to_lowercase();
+        let keywords = [
+            (DayType::PublicHoliday, ["holiday", "praznik"]),
+            (DayType::SchoolBreak, ["break", "počitnice"]),
+            (DayType::ExamDay, ["exam", "izpit"]),
+        ];
+        keywords
+            .into_iter()
+            .find(|(_, words)| words.iter().any(|word| summary.contains(word)))
+            .map(|(day_type, _)| day_type)
+            .unwrap_or(DayType::SpecialDay)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CalendarRow {
+    date: NaiveDate,
+    /// Last day (inclusive) when the row covers a range.
+    end_date: Option<NaiveDate>,
+    day_type: DayType,
+}
+
+/// First and last day (inclusive) of a calendar entry.
+type DayRange = (NaiveDate, NaiveDate, DayType);
+
+#[derive(Debug, Default)]
+pub struct Calendar {
+    days: HashMap<NaiveDate, DayType>,
+}
+
+/// Easter Sunday, anonymous Gregorian (Meeus/Jones/Butcher) algorithm.
+fn easter(year: i32) -> Option<NaiveDate> {
+    let a = year % 19;
+    let (b, c) = (year / 100, year % 100);
+    let (d, e) = (b / 4, b % 4);
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let (i, k) = (c / 4, c % 4);
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let n = h + l - 7 * m + 114;
+    NaiveDate::from_ymd_opt(year, (n / 31) as u32, (n % 31 + 1) as u32)
+}
+
+/// Work free public holidays in Slovenia.
+fn public_holidays(year: i32) -> Vec<NaiveDate> {
+    let fixed = [(1, 1), (1, 2), (2, 8), (4, 27), (5, 1), (5, 2), (6, 25), (8, 15), (10, 31), (11, 1), (12, 25), (12, 26)];
+    fixed
+        .into_iter()
+        .filter_map(|(month, day)| NaiveDate::from_ymd_opt(year, month, day))
+        .chain(easter(year).map(|sunday| sunday + Duration::days(1)))
+        .collect()
+}
+
+impl Calendar {
+    /// Reads `calendar_file` when set, `.ics` files as iCal and anything else
+    /// as csv with `date`, optional `end_date` and `day_type` columns.
+    pub fn new(config: &Config) -> Result<Self, Box<dyn Error>> {
+        let mut calendar = Calendar::default();
+        let path = match &config.calendar_file {
+            Some(path) => Path::new(path),
+            None => return Ok(calendar),
+        };
+
+        let ranges = match path.extension().and_then(|e| e.to_str()) {
+            Some("ics") => parse_ical(&fs::read_to_string(path)?)?,
+            _ => {
+                let mut reader = csv::Reader::from_path(path)?;
+                let mut ranges = Vec::new();
+                for row in reader.deserialize() {
+                    let row: CalendarRow = row?;
+                    ranges.push((row.date, row.end_date.unwrap_or(row.date), row.day_type));
+                }
+                ranges
+            },
+        };
+
+        for (start, end, day_type) in ranges {
+            for date in start.iter_days().take_while(|date| *date <= end) {
+                calendar.days.insert(date, day_type);
+            }
+        }
+        Ok(calendar)
+    }
+
+    pub fn day_type(&self, date: NaiveDate) -> DayType {
+        if let Some(day_type) = self.days.get(&date) {
+            return *day_type;
+        }
+        if public_holidays(date.year()).contains(&date) {
+            return DayType::PublicHoliday;
+        }
+        match date.weekday() {
+            Weekday::Sat | Weekday::Sun => DayType::Weekend,
+            _ => DayType::SchoolDay,
+        }
+    }
+}
+
+fn parse_ical_date(value: &str) -> Result<NaiveDate, Box<dyn Error>> {
+    // DTSTART;VALUE=DATE:20240101 or DTSTART:20240101T080000Z
+    let value = value.rsplit(':').next().unwrap_or_default();
+    match NaiveDate::parse_from_str(value.get(..8).unwrap_or_default(), "%Y%m%d") {
+        Ok(d) => Ok(d),
+        Err(e) => Err(format!("Error parsing calendar date {}: {}", value, e).into()),
+    }
+}
+
+/// Last day of an event from its `DTEND`, which is exclusive in iCal: an all
+/// day event ends the day before, a timed one on its day unless it ends at
+/// midnight.
+fn parse_ical_end(line: &str) -> Result<NaiveDate, Box<dyn Error>> {
+    let end = parse_ical_date(line)?;
+    let value = line.rsplit(':').next().unwrap_or_default();
+    match value.get(8..) {
+        Some(time) if !time.is_empty() && !time.starts_with("T000000") => Ok(end),
+        _ => Ok(end - Duration::days(1)),
+    }
+}
+
+/// Events of an iCal file as inclusive date ranges, events without `DTEND`
+/// last a single day.
+fn parse_ical(content: &str) -> Result<Vec<DayRange>, Box<dyn Error>> {
+    // long lines are folded onto continuation lines starting with a space
+    let unfolded = content.replace("\r\n", "\n").replace("\n ", "").replace("\n\t", "");
+    let mut ranges = Vec::new();
+    let (mut start, mut end, mut summary, mut categories) = (None, None, String::new(), String::new());
+
+    for line in unfolded.lines() {
+        let name = line.split([':', ';']).next().unwrap_or_default();
+        let value = line.split_once(':').map(|(_, v)| v).unwrap_or_default();
+        match name {
+            "BEGIN" if value == "VEVENT" => {
+                (start, end, summary, categories) = (None, None, String::new(), String::new());
+            },
+            "DTSTART" => start = Some(parse_ical_date(line)?),
+            "DTEND" => end = Some(parse_ical_end(line)?),
+            "SUMMARY" => summary = value.to_string(),
+            "CATEGORIES" => categories = value.to_string(),
+            "END" if value == "VEVENT" => match start {
+                Some(start) => ranges.push((start, end.unwrap_or(start).max(start), DayType::from_event(&categories, &summary))),
+                None => println!("Skipping calendar event without DTSTART: {}", summary),
+            },
+            _ => (),
+        }
+    }
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn event(start: &str, end: &str) -> String {
+        format!("BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\n{}\r\n{}\r\nSUMMARY:Exam\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n", start, end)
+    }
+
+    #[test]
+    fn easter_sundays() {
+        assert_eq!(easter(2023), Some(date(2023, 4, 9)));
+        assert_eq!(easter(2024), Some(date(2024, 3, 31)));
+        assert_eq!(easter(2025), Some(date(2025, 4, 20)));
+    }
+
+    #[test]
+    fn holidays_include_easter_monday() {
+        let holidays = public_holidays(2024);
+        assert!(holidays.contains(&date(2024, 4, 1)));
+        assert!(holidays.contains(&date(2024, 1, 1)));
+        assert!(!holidays.contains(&date(2024, 3, 31)));
+    }
+
+    #[test]
+    fn all_day_events_end_the_day_before() {
+        let ranges = parse_ical(&event("DTSTART;VALUE=DATE:20240101", "DTEND;VALUE=DATE:20240103")).unwrap();
+        assert_eq!(ranges, vec![(date(2024, 1, 1), date(2024, 1, 2), DayType::ExamDay)]);
+    }
+
+    #[test]
+    fn timed_events_keep_their_end_day() {
+        let ranges = parse_ical(&event("DTSTART:20240101T080000Z", "DTEND:20240102T100000Z")).unwrap();
+        assert_eq!(ranges, vec![(date(2024, 1, 1), date(2024, 1, 2), DayType::ExamDay)]);
+
+        let ranges = parse_ical(&event("DTSTART:20240101T080000", "DTEND:20240102T000000")).unwrap();
+        assert_eq!(ranges, vec![(date(2024, 1, 1), date(2024, 1, 1), DayType::ExamDay)]);
+    }
+}
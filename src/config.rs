@@ -0,0 +1,443 @@
+use std::{collections::BTreeMap, env, error::Error, fs::File};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    calendar::DayType,
+    features::{RoomEncoding, TimeFeature},
+    occupancy::{ConflictRule, OccupancySourceKind},
+    rooms::RoomNormalization,
+    series::SeriesFeature,
+    solar::Site,
+    target::{OccupancyTarget, Target, TargetPosition},
+    scalers::{robust_scaler::DEFAULT_QUANTILE_RANGE, ScalerKind, TransformKind},
+    scaling::{SENSOR_FIELDS, WEATHER_FIELDS},
+    SensorLocation,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Sensor exports, parsed in order; later files win on duplicate minutes.
+    pub sensor_files: Vec<String>,
+    /// Weather station exports, parsed in order; later files win on duplicate minutes.
+    pub weather_files: Vec<String>,
+    /// School timetable with the number of people per room and slot.
+    pub school_file: String,
+    /// Rooms whose counts come from somewhere else than `school_file`.
+    pub occupancy_sources: BTreeMap<SensorLocation, OccupancySourceKind>,
+    /// What to do with differing counts for the same room and minute.
+    pub occupancy_conflicts: ConflictRule,
+    /// Holidays, school breaks, exam and other special days, as csv or iCal.
+    /// Weekends and Slovenian public holidays are known without it.
+    pub calendar_file: Option<String>,
+    /// Room metadata csv, see `rooms::Rooms::new`.
+    pub rooms_file: Option<String>,
+    /// Directory the datasets are written to, each in its own sub directory.
+    pub output_dir: String,
+    /// Name of the dataset directory, defaults to the start of the provenance hash.
+    pub dataset_name: Option<String>,
+    /// Replace an existing dataset directory even if it holds a different dataset.
+    #[serde(skip)]
+    pub force: bool,
+    /// Also write the legacy `fold_k/train.csv` + `fold_k/test.csv` layout.
+    pub materialize_splits: bool,
+    pub folds: usize,
+    /// Minutes per window.
+    pub window_size: usize,
+    /// Byte the 32 byte fold shuffling seed is filled with.
+    pub seed: u8,
+    /// First hour of the day (inclusive) that is kept.
+    pub start_hour: u32,
+    /// Last hour of the day (exclusive) that is kept, 24 keeps the day until midnight.
+    pub end_hour: u32,
+    /// Locations dropped after merging, they have no occupancy labels.
+    pub excluded_locations: Vec<SensorLocation>,
+    /// Rooms whose readings are joined to every row by minute, e.g. the
+    /// corridor and dining room. They can be excluded at the same time.
+    pub context_locations: Vec<SensorLocation>,
+    /// Sensor fields of the context rooms, see `features::context_columns`.
+    pub context_fields: Vec<String>,
+    /// Days of these types are dropped before windowing.
+    pub excluded_day_types: Vec<DayType>,
+    /// Time encodings written in front of the sensor columns, in this order.
+    pub time_features: Vec<TimeFeature>,
+    /// Write the `day_type` column, see `DayType::code`.
+    pub day_type_feature: bool,
+    /// Write the timetable slot columns, see `features::push_slot_values`.
+    pub slot_features: bool,
+    /// Write the room into every row, after the slot columns.
+    pub room_encoding: Option<RoomEncoding>,
+    /// Lags, differences, rolling statistics and EWMAs of sensor and weather
+    /// columns, computed per room and day before windowing.
+    pub series_features: Vec<SeriesFeature>,
+    /// Fill in missing dew point and absolute humidity from temperature and RH.
+    pub reconstruct_humidity: bool,
+    /// Write the indoor-outdoor temperature and absolute humidity differences
+    /// and the inconsistent humidity flag, see `psychrometrics`.
+    pub psychrometric_features: bool,
+    /// CO2 mass balance occupancy and air exchange estimates, see `mass_balance`.
+    pub mass_balance: Option<MassBalance>,
+    /// Flag window openings, see `ventilation::detect_events`.
+    pub ventilation: Option<VentilationDetection>,
+    /// Solar position and clear sky irradiance at this site, see `solar`.
+    pub solar: Option<Site>,
+    /// Write the static room columns of `rooms_file`, see `rooms::ROOM_COLUMNS`.
+    pub room_features: bool,
+    /// Sensor fields divided by a room property, e.g. CO2 per m³.
+    pub room_normalizations: Vec<RoomNormalization>,
+    /// How `people` targets are encoded, see `OccupancyTarget`.
+    pub occupancy_target: OccupancyTarget,
+    /// Columns to predict, more than one for multi-target output.
+    pub targets: Vec<Target>,
+    pub target_position: TargetPosition,
+    pub scaling: ScalingMode,
+    /// Scaler for every feature without an entry in `sensor_scalers` / `weather_scalers`.
+    pub default_scaler: ScalerKind,
+    /// Scaler per sensor field, overriding `default_scaler`. Entries of a
+    /// config file are added to the defaults (`rh` unscaled) rather than
+    /// replacing them, set `"rh": "robust"` to scale RH.
+    pub sensor_scalers: BTreeMap<String, ScalerKind>,
+    /// Scaler per weather field, overriding `default_scaler`.
+    pub weather_scalers: BTreeMap<String, ScalerKind>,
+    /// Power transform per sensor field, applied before its scaler.
+    pub sensor_transforms: BTreeMap<String, TransformKind>,
+    /// Power transform per weather field, applied before its scaler.
+    pub weather_transforms: BTreeMap<String, TransformKind>,
+    /// Also fit the sensor scalers per room, rooms differ in volume and CO2 baseline.
+    /// The scalers fitted on all rooms are kept for rooms without their own.
+    pub scalers_per_location: bool,
+    /// Subtract every room's level before school from the rest of its day.
+    pub baseline: Option<Baseline>,
+    /// Fit the scalers from bounded memory sketches (t-digest quantiles,
+    /// Welford mean and variance) instead of sorting a copy of every column.
+    /// This bounds the memory of fitting, the parsed data is still held in
+    /// memory as a whole. See `scalers::sketch` for the approximation error.
+    pub streaming_scalers: bool,
+    /// Lower and upper quantile (in percent) the robust scaler divides by, sklearn's `quantile_range`.
+    pub robust_quantile_range: (f32, f32),
+    /// Scalers saved by an earlier run, applied instead of fitting new ones.
+    /// Only used with global scaling.
+    pub scalers_file: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScalingMode {
+    /// Scalers are fitted once on everything that was parsed, nights and
+    /// test days included.
+    Global,
+    /// Scalers are fitted per split on the minutes of its training windows
+    /// and applied to train and test separately.
+    PerSplit,
+}
+
+/// Per day baseline: the median of a field over `start_hour..end_hour` of a
+/// room is subtracted from all of that room's readings of the day, before scaling.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Baseline {
+    /// Sensor fields the baseline is subtracted from.
+    pub fields: Vec<String>,
+    /// First hour (inclusive) of the baseline period.
+    pub start_hour: u32,
+    /// Last hour (exclusive) of the baseline period.
+    pub end_hour: u32,
+}
+
+impl Default for Baseline {
+    fn default() -> Self {
+        Baseline {
+            fields: vec!["co2".to_string()],
+            start_hour: 4,
+            end_hour: 7,
+        }
+    }
+}
+
+/// Single zone CO2 mass balance of a room, `V·dC/dt = G·n + Q·(C_out - C)`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MassBalance {
+    /// Room volume in m³, rooms without one use the one in `rooms_file` or
+    /// else `default_volume`.
+    pub volumes: BTreeMap<SensorLocation, f32>,
+    pub default_volume: f32,
+    /// CO2 exhaled per person in m³/h.
+    pub generation_rate: f32,
+    /// Outdoor CO2 in ppm.
+    pub outdoor_co2: f32,
+    /// Air changes per hour for days without enough decay to estimate them from.
+    pub default_air_exchange: f32,
+    /// Minutes the CO2 slope is taken over.
+    pub slope_minutes: usize,
+    /// Write the `co2_occupancy` and `air_exchange_rate` columns.
+    pub features: bool,
+    /// Write `mass_balance_baseline.csv` with the estimate next to the counted people.
+    pub baseline_file: bool,
+}
+
+impl Default for MassBalance {
+    fn default() -> Self {
+        MassBalance {
+            volumes: BTreeMap::new(),
+            // 60 m² classroom, 3 m ceiling
+            default_volume: 180.,
+            // seated teenager, about 0.0043 l/s
+            generation_rate: 0.0155,
+            outdoor_co2: 420.,
+            default_air_exchange: 0.5,
+            slope_minutes: 10,
+            features: true,
+            baseline_file: true,
+        }
+    }
+}
+
+/// Thresholds of the window opening detector.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VentilationDetection {
+    /// Minutes the CO2 and temperature drops are measured over.
+    pub slope_minutes: usize,
+    /// CO2 drop in ppm per minute, faster than a room emptying.
+    pub co2_drop: f32,
+    /// Indoor temperature drop in °C over `slope_minutes`.
+    pub temperature_drop: f32,
+    /// The temperature drop is only required when it is at least this
+    /// much (°C) colder outside than inside.
+    pub min_temperature_difference: f32,
+    /// Shorter drops are not events.
+    pub min_minutes: usize,
+}
+
+impl Default for VentilationDetection {
+    fn default() -> Self {
+        VentilationDetection {
+            slope_minutes: 5,
+            co2_drop: 20.,
+            temperature_drop: 0.3,
+            min_temperature_difference: 5.,
+            min_minutes: 2,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            sensor_files: vec![
+                "data/jan_feb_mar_ajdovscina_iaq.csv".to_string(),
+                "data/apr_maj_jun_ajdovscina_iaq.csv".to_string(),
+            ],
+            weather_files: vec![
+                "data/vreme_jan_feb_mar.csv".to_string(),
+                "data/vreme_apr_maj_jun.csv".to_string(),
+            ],
+            school_file: "data/school_data.csv".to_string(),
+            occupancy_sources: BTreeMap::new(),
+            occupancy_conflicts: ConflictRule::default(),
+            calendar_file: None,
+            rooms_file: None,
+            output_dir: "out".to_string(),
+            dataset_name: None,
+            force: false,
+            materialize_splits: false,
+            folds: 10,
+            window_size: 180,
+            seed: 42,
+            start_hour: 4,
+            end_hour: 16,
+            excluded_locations: vec![
+                SensorLocation::Jedilnica,
+                SensorLocation::Hodnik,
+                SensorLocation::Zbornica,
+            ],
+            context_locations: vec![],
+            context_fields: vec!["co2".to_string()],
+            excluded_day_types: vec![],
+            time_features: vec![TimeFeature::MonthOneHot, TimeFeature::DayOfMonth, TimeFeature::MinuteOfDay],
+            day_type_feature: false,
+            slot_features: false,
+            room_encoding: None,
+            series_features: vec![],
+            reconstruct_humidity: false,
+            psychrometric_features: false,
+            mass_balance: None,
+            ventilation: None,
+            solar: None,
+            room_features: false,
+            room_normalizations: vec![],
+            occupancy_target: OccupancyTarget::Count,
+            targets: vec![Target::people()],
+            target_position: TargetPosition::Last,
+            scaling: ScalingMode::Global,
+            default_scaler: ScalerKind::Robust,
+            sensor_scalers: default_sensor_scalers(),
+            weather_scalers: BTreeMap::new(),
+            sensor_transforms: BTreeMap::new(),
+            weather_transforms: BTreeMap::new(),
+            scalers_per_location: false,
+            baseline: None,
+            streaming_scalers: false,
+            robust_quantile_range: DEFAULT_QUANTILE_RANGE,
+            scalers_file: None,
+        }
+    }
+}
+
+fn default_sensor_scalers() -> BTreeMap<String, ScalerKind> {
+    // RH is already bounded between 0 and 100
+    BTreeMap::from([("rh".to_string(), ScalerKind::None)])
+}
+
+impl Config {
+    /// Reads the config file given with `--config` (if any), then applies the
+    /// remaining command line flags on top of it.
+    pub fn from_args() -> Result<Self, Box<dyn Error>> {
+        let args: Vec<String> = env::args().skip(1).collect();
+
+        let mut config = match args.iter().position(|a| a == "--config") {
+            Some(i) => match args.get(i + 1) {
+                Some(path) => Config::from_file(path)?,
+                None => return Err("--config expects a file".into()),
+            },
+            None => Config::default(),
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => {
+                    args.next();
+                },
+                "--output" => match args.next() {
+                    Some(dir) => config.output_dir = dir,
+                    None => return Err("--output expects a directory".into()),
+                },
+                "--name" => match args.next() {
+                    Some(name) => config.dataset_name = Some(name),
+                    None => return Err("--name expects a dataset name".into()),
+                },
+                "--scalers" => match args.next() {
+                    Some(file) => config.scalers_file = Some(file),
+                    None => return Err("--scalers expects a file".into()),
+                },
+                "--force" => config.force = true,
+                "--materialize-splits" => config.materialize_splits = true,
+                _ => return Err(format!("unknown argument: {}", arg).into()),
+            }
+        }
+
+        if config.folds == 0 || config.window_size == 0 {
+            return Err("folds and window_size have to be positive".into());
+        }
+        if config.start_hour >= config.end_hour || config.end_hour > 24 {
+            return Err(format!("invalid hours: {}..{}", config.start_hour, config.end_hour).into());
+        }
+        if config.scalers_file.is_some() && config.scaling != ScalingMode::Global {
+            return Err("scalers_file can only be used with global scaling".into());
+        }
+        let (low, high) = config.robust_quantile_range;
+        if !(0. ..=100.).contains(&low) || !(0. ..=100.).contains(&high) || low >= high {
+            return Err(format!("invalid robust_quantile_range: ({}, {})", low, high).into());
+        }
+        if let Some(baseline) = &config.baseline {
+            if baseline.start_hour >= baseline.end_hour || baseline.end_hour > 24 {
+                return Err(format!("invalid baseline hours: {}..{}", baseline.start_hour, baseline.end_hour).into());
+            }
+        }
+        if let Some(mass_balance) = &config.mass_balance {
+            let volumes = mass_balance.volumes.values().chain([&mass_balance.default_volume]);
+            if volumes.chain([&mass_balance.generation_rate]).any(|v| *v <= 0.) || mass_balance.slope_minutes == 0 {
+                return Err("mass_balance volumes, generation_rate and slope_minutes have to be positive".into());
+            }
+        }
+        if config.ventilation.as_ref().is_some_and(|v| v.slope_minutes == 0 || v.min_minutes == 0) {
+            return Err("ventilation slope_minutes and min_minutes have to be positive".into());
+        }
+        // the detector compares the room temperature with the outside one
+        if config.ventilation.is_some() && config.baseline.as_ref().is_some_and(|b| b.fields.iter().any(|f| f == "temperature")) {
+            return Err("ventilation can not be used with a temperature baseline".into());
+        }
+        if config.solar.as_ref().is_some_and(|s| !(-90. ..=90.).contains(&s.latitude) || !(-180. ..=180.).contains(&s.longitude)) {
+            return Err("solar latitude or longitude out of range".into());
+        }
+        if let OccupancyTarget::Bins { edges } = &config.occupancy_target {
+            if edges.is_empty() || edges.windows(2).any(|pair| pair[0] >= pair[1]) {
+                return Err("occupancy_target bin edges have to be ascending".into());
+            }
+        }
+        if config.targets.is_empty() {
+            return Err("targets can not be empty".into());
+        }
+        let mut target_columns = Vec::new();
+        for target in config.targets.iter() {
+            let field = target.field.as_str();
+            let known = SENSOR_FIELDS.contains(&field) || WEATHER_FIELDS.contains(&field);
+            if !known && field != "people" && field != "outside_temperature" {
+                return Err(format!("unknown target field: {}", field).into());
+            }
+            let column = target.column(&config.occupancy_target);
+            if target_columns.contains(&column) {
+                return Err(format!("duplicate target: {}", column).into());
+            }
+            target_columns.push(column);
+        }
+        if (config.room_features || !config.room_normalizations.is_empty()) && config.rooms_file.is_none() {
+            return Err("room_features and room_normalizations need a rooms_file".into());
+        }
+        for normalization in config.room_normalizations.iter() {
+            if !SENSOR_FIELDS.contains(&normalization.field.as_str()) {
+                return Err(format!("unknown field in room_normalizations: {}", normalization.field).into());
+            }
+        }
+        for feature in config.series_features.iter() {
+            let field = feature.field.as_str();
+            if !SENSOR_FIELDS.contains(&field) && !WEATHER_FIELDS.contains(&field) && field != "outside_temperature" {
+                return Err(format!("unknown field in series_features: {}", field).into());
+            }
+        }
+        let baseline_fields = config.baseline.iter().flat_map(|b| b.fields.iter());
+        let sensor_names = config.sensor_scalers.keys().chain(config.sensor_transforms.keys()).chain(config.context_fields.iter());
+        for name in sensor_names.chain(baseline_fields) {
+            if !SENSOR_FIELDS.contains(&name.as_str()) {
+                return Err(format!("unknown sensor field: {}", name).into());
+            }
+        }
+        for name in config.weather_scalers.keys().chain(config.weather_transforms.keys()) {
+            if !WEATHER_FIELDS.contains(&name.as_str()) {
+                return Err(format!("unknown weather field: {}", name).into());
+            }
+        }
+        Ok(config)
+    }
+
+    /// Longest target horizon, in minutes.
+    pub fn max_horizon(&self) -> usize {
+        self.targets.iter().map(|target| target.horizon).max().unwrap_or_default()
+    }
+
+    pub fn sensor_scaler(&self, field: &str) -> ScalerKind {
+        *self.sensor_scalers.get(field).unwrap_or(&self.default_scaler)
+    }
+
+    pub fn weather_scaler(&self, field: &str) -> ScalerKind {
+        *self.weather_scalers.get(field).unwrap_or(&self.default_scaler)
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Error opening config {}: {}", path, e).into()),
+        };
+        let mut config: Config = match serde_json::from_reader(file) {
+            Ok(c) => c,
+            Err(e) => return Err(format!("Error parsing config {}: {}", path, e).into()),
+        };
+        let mut sensor_scalers = default_sensor_scalers();
+        sensor_scalers.append(&mut config.sensor_scalers);
+        config.sensor_scalers = sensor_scalers;
+        Ok(config)
+    }
+}
@@ -0,0 +1,258 @@
+use std::f32::consts::PI;
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    mass_balance::MASS_BALANCE_COLUMNS,
+    psychrometrics::PSYCHROMETRIC_COLUMNS,
+    rooms::ROOM_COLUMNS,
+    scaling::sensor_fields,
+    solar::SOLAR_COLUMNS,
+    timetable::slot_position,
+    SensedPeople,
+    Sensor,
+    SensorLocation,
+    ventilation::VENTILATION_COLUMN,
+};
+
+/// Ways of encoding when a minute was recorded, any number of them can be
+/// selected with `Config::time_features`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeFeature {
+    /// `jan` .. `dec`, one-hot month.
+    MonthOneHot,
+    /// `day`, day of the month.
+    DayOfMonth,
+    /// `time`, minutes since midnight.
+    MinuteOfDay,
+    /// `time_sin`, `time_cos`, so 23:59 is next to 00:00.
+    TimeOfDayCyclical,
+    /// `day_of_year_sin`, `day_of_year_cos`.
+    DayOfYearCyclical,
+    /// `mon` .. `sun`, one-hot day of the week.
+    DayOfWeekOneHot,
+    /// `school_week`, weeks since the school year started on 1 September, from 1.
+    WeekOfSchoolYear,
+    /// `is_weekend`.
+    IsWeekend,
+}
+
+static MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+static WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+fn one_hot(index: usize, len: usize) -> impl Iterator<Item = f32> {
+    (0..len).map(move |i| if i == index { 1. } else { 0. })
+}
+
+fn cyclical(fraction: f32) -> [f32; 2] {
+    let angle = 2. * PI * fraction;
+    [angle.sin(), angle.cos()]
+}
+
+fn school_year_start(date: NaiveDate) -> NaiveDate {
+    let year = if date.month() >= 9 { date.year() } else { date.year() - 1 };
+    NaiveDate::from_ymd_opt(year, 9, 1).unwrap_or(date)
+}
+
+impl TimeFeature {
+    pub fn columns(&self) -> Vec<&'static str> {
+        match self {
+            TimeFeature::MonthOneHot => MONTHS.to_vec(),
+            TimeFeature::DayOfMonth => vec!["day"],
+            TimeFeature::MinuteOfDay => vec!["time"],
+            TimeFeature::TimeOfDayCyclical => vec!["time_sin", "time_cos"],
+            TimeFeature::DayOfYearCyclical => vec!["day_of_year_sin", "day_of_year_cos"],
+            TimeFeature::DayOfWeekOneHot => WEEKDAYS.to_vec(),
+            TimeFeature::WeekOfSchoolYear => vec!["school_week"],
+            TimeFeature::IsWeekend => vec!["is_weekend"],
+        }
+    }
+
+    /// Values of `columns` for the minute `ndt`.
+    pub fn push_values(&self, ndt: &NaiveDateTime, values: &mut Vec<f32>) {
+        let date = ndt.date();
+        let minute = (ndt.num_seconds_from_midnight() / 60) as f32;
+
+        match self {
+            TimeFeature::MonthOneHot => values.extend(one_hot(date.month0() as usize, 12)),
+            TimeFeature::DayOfMonth => values.push(date.day() as f32),
+            TimeFeature::MinuteOfDay => values.push(minute),
+            TimeFeature::TimeOfDayCyclical => values.extend(cyclical(minute / 1440.)),
+            TimeFeature::DayOfYearCyclical => {
+                let days_in_year = if date.leap_year() { 366. } else { 365. };
+                values.extend(cyclical(date.ordinal0() as f32 / days_in_year));
+            },
+            TimeFeature::DayOfWeekOneHot => {
+                values.extend(one_hot(date.weekday().num_days_from_monday() as usize, 7))
+            },
+            TimeFeature::WeekOfSchoolYear => {
+                values.push(((date - school_year_start(date)).num_days() / 7 + 1) as f32)
+            },
+            TimeFeature::IsWeekend => {
+                let weekend = matches!(date.weekday(), Weekday::Sat | Weekday::Sun);
+                values.push(if weekend { 1. } else { 0. });
+            },
+        }
+    }
+}
+
+/// How the room is written into every row, `windows.csv` always has its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomEncoding {
+    /// `room_index`, see `SensorLocation::index`.
+    Index,
+    /// `room_u4c`, `room_jedilnica`, .. one column per room.
+    OneHot,
+}
+
+impl RoomEncoding {
+    pub fn columns(&self) -> Vec<String> {
+        match self {
+            RoomEncoding::Index => vec!["room_index".to_string()],
+            RoomEncoding::OneHot => SensorLocation::ALL
+                .iter()
+                .map(|location| format!("room_{}", location.column_name()))
+                .collect(),
+        }
+    }
+
+    pub fn push_values(&self, location: &SensorLocation, values: &mut Vec<f32>) {
+        match self {
+            RoomEncoding::Index => values.push(location.index() as f32),
+            RoomEncoding::OneHot => values.extend(one_hot(location.index(), SensorLocation::ALL.len())),
+        }
+    }
+}
+
+/// Columns of `push_slot_values`.
+pub static SLOT_COLUMNS: [&str; 5] = ["slot", "slot_elapsed", "slot_remaining", "is_break", "has_lesson"];
+
+/// Timetable slot of the minute (-1 outside of the timetable), minutes since
+/// the slot started and until it ends, whether it is the break after the
+/// lesson and whether the room has a lesson at that minute: one is scheduled
+/// in the slot and it is not the break.
+pub fn push_slot_values(ndt: &NaiveDateTime, people: &SensedPeople, values: &mut Vec<f32>) {
+    let is_break = match slot_position(ndt) {
+        Some(position) => {
+            values.extend([position.slot as f32, position.elapsed as f32, position.remaining as f32]);
+            position.is_break
+        },
+        None => {
+            values.extend([-1., 0., 0.]);
+            false
+        },
+    };
+    values.push(if is_break { 1. } else { 0. });
+    values.push(if people.scheduled && !is_break { 1. } else { 0. });
+}
+
+/// `{room}_{field}` columns of the context rooms, e.g. `hodnik_co2`.
+pub fn context_columns(config: &Config) -> Vec<String> {
+    config
+        .context_locations
+        .iter()
+        .flat_map(|location| config.context_fields.iter().map(move |field| format!("{}_{}", location.column_name(), field)))
+        .collect()
+}
+
+/// Values of `context_columns` from all sensors of one minute, NaN for rooms
+/// that did not report the field, 0 would look like a real reading. NaN is
+/// not fitted by the scalers and stays NaN when scaled.
+pub fn push_context_values(sensors: &[Sensor], config: &Config, values: &mut Vec<f32>) {
+    for location in config.context_locations.iter() {
+        let sensor = sensors.iter().find(|sensor| sensor.location == *location);
+        for field in config.context_fields.iter() {
+            let value = sensor
+                .and_then(|sensor| sensor_fields(sensor).into_iter().find(|(name, _)| name == field))
+                .and_then(|(_, value)| value);
+            values.push(value.unwrap_or(f32::NAN));
+        }
+    }
+}
+
+/// Derived columns that are already bounded, 0/1 flags and sin/cos pairs.
+/// They are never scaled.
+pub static UNSCALED_COLUMNS: [&str; 9] = [
+    "humidity_inconsistent",
+    "ventilation_event",
+    "solar_azimuth_sin",
+    "solar_azimuth_cos",
+    "orientation_sin",
+    "orientation_cos",
+    "ventilation_natural",
+    "ventilation_mechanical",
+    "ventilation_hybrid",
+];
+
+/// Derived columns computed from the raw readings, see `Sensor::derived`.
+pub fn raw_derived_columns(config: &Config) -> Vec<String> {
+    let mut columns = vec![];
+    if config.psychrometric_features {
+        columns.extend(PSYCHROMETRIC_COLUMNS);
+    }
+    if config.mass_balance.as_ref().is_some_and(|m| m.features) {
+        columns.extend(MASS_BALANCE_COLUMNS);
+    }
+    if config.room_features {
+        columns.extend(ROOM_COLUMNS);
+    }
+    let mut columns: Vec<String> = columns.into_iter().map(String::from).collect();
+    columns.extend(config.room_normalizations.iter().map(|normalization| normalization.column()));
+    if config.solar.is_some() {
+        columns.extend(SOLAR_COLUMNS.map(String::from));
+    }
+    columns
+}
+
+/// Columns of the per minute derived feature values, written after the weather columns.
+pub fn derived_columns(config: &Config) -> Vec<String> {
+    let mut columns = raw_derived_columns(config);
+    columns.extend(context_columns(config));
+    if config.ventilation.is_some() {
+        columns.push(VENTILATION_COLUMN.to_string());
+    }
+    columns.extend(config.series_features.iter().map(|feature| feature.column()));
+    columns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::record;
+
+    fn slot_values(at: &str) -> Vec<f32> {
+        let (ndt, _, people, ..) = record(at, &[], 20);
+        let mut values = Vec::new();
+        push_slot_values(&ndt, &people, &mut values);
+        values
+    }
+
+    #[test]
+    fn missing_context_rooms_are_nan() {
+        let config = Config {
+            context_locations: vec![SensorLocation::U11, SensorLocation::Hodnik],
+            context_fields: vec!["co2".to_string(), "temperature".to_string()],
+            ..Config::default()
+        };
+        let sensor = record("2023-01-09 08:00", &[("co2", 800.)], 0).1;
+        let mut values = Vec::new();
+        push_context_values(&[sensor], &config, &mut values);
+
+        assert_eq!(context_columns(&config), ["u11_co2", "u11_temperature", "hodnik_co2", "hodnik_temperature"]);
+        assert_eq!(values[0], 800.);
+        assert!(values[1..].iter().all(|value| value.is_nan()), "{:?}", values);
+    }
+
+    #[test]
+    fn breaks_have_no_lesson() {
+        assert_eq!(slot_values("2023-01-09 08:10"), vec![1., 10., 40., 0., 1.]);
+        assert_eq!(slot_values("2023-01-09 08:47"), vec![1., 47., 3., 1., 0.]);
+        // the long break after the third lesson
+        assert_eq!(slot_values("2023-01-09 10:30"), vec![3., 50., 20., 1., 0.]);
+        assert_eq!(slot_values("2023-01-09 07:45"), vec![0., 15., 15., 0., 1.]);
+    }
+}
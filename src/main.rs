@@ -1,22 +1,71 @@
+mod calendar;
+mod config;
+mod features;
+mod manifest;
+mod mass_balance;
+mod occupancy;
+mod output;
+mod psychrometrics;
+mod rooms;
 mod scalers;
-
-use std::{error::Error, fs::{File, self}, sync::Mutex, collections::HashMap, ops::Add, path::Path};
-use crate::scalers::robust_scaler::RobustScaler;
-use chrono::{NaiveDateTime, Timelike, Duration, NaiveDate, NaiveTime, Datelike};
+mod scaling;
+mod series;
+mod solar;
+mod target;
+#[cfg(test)]
+mod testing;
+mod timetable;
+mod ventilation;
+
+use std::{error::Error, fs::{File, self}, collections::{BTreeMap, HashMap, HashSet}, ops::Add, path::Path};
+use crate::{
+    calendar::{Calendar, DayType},
+    config::{Config, ScalingMode},
+    manifest::Manifest,
+    mass_balance::{add_mass_balance, estimate, write_baseline},
+    occupancy::{resolve_conflicts, write_conflicts},
+    output::DatasetDir,
+    psychrometrics::add_psychrometrics,
+    rooms::{add_room_features, Rooms},
+    scaling::{
+        fit_data_scalers,
+        fit_split_scalers,
+        scale_location_day,
+        scale_sensor_data,
+        scale_weather_data,
+        sensor_fields,
+        subtract_baselines,
+        weather_fields,
+        write_baselines,
+        BaselineLevels,
+        FittedScalers,
+        SENSOR_FIELDS,
+        WEATHER_FIELDS,
+    },
+    solar::add_solar_features,
+    target::TargetPosition,
+    ventilation::{detect_events, write_events, VentilationEvent},
+};
+use chrono::{NaiveDateTime, Timelike, Duration, NaiveDate, NaiveTime};
 use csv::Reader;
-use once_cell::sync::Lazy;
 use rand::{seq::SliceRandom, rngs::StdRng, SeedableRng};
 use rayon::prelude::*;
-use serde::Serialize;
-use std::time::Instant;
+use serde::{Deserialize, Serialize};
+use std::{process::ExitCode, time::Instant};
 use dashmap::DashMap;
 
 
-static FOLDS: i32 = 10;
-static START_HOUR: u32 = 3;
-static END_HOUR: u32 = 16;
-static WINDOW_SIZE: usize = 180;
-const SEED: [u8; 32] = [42; 32];
+/// One minute of merged data for a single location, the last element holds
+/// the derived feature values in the order of `features::derived_columns`.
+type MinuteRecord = (NaiveDateTime, Sensor, SensedPeople, WeatherPoint, Vec<f32>);
+/// Windows of consecutive minutes and the minutes after the last window that
+/// forecast targets look ahead to, grouped by day.
+type DayWindows = HashMap<NaiveDate, (Vec<Vec<MinuteRecord>>, Vec<MinuteRecord>)>;
+type ParsedData = (
+    DashMap<NaiveDateTime, Vec<Sensor>>,
+    DashMap<NaiveDateTime, Vec<SensedPeople>>,
+    DashMap<NaiveDateTime, WeatherPoint>,
+);
 
 
 #[derive(Debug)]
@@ -36,9 +85,11 @@ pub struct Sensor {
     rh: Option<f32>,
     temperature: Option<f32>,
     vec_eq_co2: Option<f32>,
+    /// Features computed from the raw readings, before anything is scaled.
+    derived: Vec<f32>,
 }
 
-#[derive(Debug, Clone,PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum SensorLocation {
     U4c,
     Jedilnica,
@@ -50,6 +101,29 @@ pub enum SensorLocation {
     Zbornica,
 }
 
+impl SensorLocation {
+    pub const ALL: [SensorLocation; 8] = [
+        SensorLocation::U4c,
+        SensorLocation::Jedilnica,
+        SensorLocation::U4b,
+        SensorLocation::Hodnik,
+        SensorLocation::Soba18,
+        SensorLocation::U11,
+        SensorLocation::U3a,
+        SensorLocation::Zbornica,
+    ];
+
+    /// Lower case name used in column names.
+    pub fn column_name(&self) -> String {
+        format!("{:?}", self).to_lowercase()
+    }
+
+    /// Stable index of the room, e.g. for an embedding.
+    pub fn index(&self) -> usize {
+        SensorLocation::ALL.iter().position(|l| l == self).unwrap_or_default()
+    }
+}
+
 #[derive(Debug)]
 pub enum SensorValue {
     DewPoint(f32),
@@ -62,48 +136,137 @@ pub enum SensorValue {
     VecEqCo2(f32),
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// One output row: the window it belongs to followed by the values of
+/// `TargetRow::columns`. Which columns there are depends on the config.
+#[derive(Debug, Clone)]
 pub struct TargetRow {
     window_id: i32,
-    jan: f32,
-    feb: f32,
-    mar: f32,
-    apr: f32,
-    may: f32,
-    jun: f32,
-    jul: f32,
-    aug: f32,
-    sep: f32,
-    oct: f32,
-    nov: f32,
-    dec: f32,
-    day: f32,
-    time: f32,
-    dew_point: f32,
-    luminance: f32,
-    voc_index: f32,
-    co2: f32,
-    abs_humidity: f32,
-    rh: f32,
-    temperature: f32,
-    vec_eq_co2: f32,
-    outside_temperature: f32,
-    avg_temperature: f32,
-    min_temperature: f32,
-    max_temperature: f32,
-    rel_humidity: f32,
-    avg_rel_humidity: f32,
-    min_rel_humidity: f32,
-    max_rel_humidity: f32,
-    precipitation: f32,
-    wind_speed: f32,
-    people: f32,
+    values: Vec<f32>,
+}
+
+impl Serialize for TargetRow {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTuple;
+
+        let mut tuple = serializer.serialize_tuple(self.values.len() + 1)?;
+        tuple.serialize_element(&self.window_id)?;
+        for value in self.values.iter() {
+            tuple.serialize_element(value)?;
+        }
+        tuple.end()
+    }
+}
+
+impl TargetRow {
+    pub fn new(
+        window_id: i32,
+        record: &MinuteRecord,
+        day: &LocationDay,
+        minutes: &BTreeMap<NaiveDateTime, &MinuteRecord>,
+        config: &Config,
+    ) -> Self {
+        let (ndt, sensor, sensed_people, weather, derived) = record;
+        let targets = target::target_values(record, minutes, config);
+        let mut values = Vec::new();
+        if config.target_position == TargetPosition::First {
+            values.extend(targets.iter());
+        }
+        for feature in config.time_features.iter() {
+            feature.push_values(ndt, &mut values);
+        }
+        if config.day_type_feature {
+            values.push(day.day_type.code());
+        }
+        if config.slot_features {
+            features::push_slot_values(ndt, sensed_people, &mut values);
+        }
+        if let Some(encoding) = config.room_encoding {
+            encoding.push_values(&day.location, &mut values);
+        }
+        values.extend(sensor_fields(sensor).map(|(_, value)| value.unwrap_or_default()));
+        values.extend(weather_fields(weather).map(|(_, value)| value));
+        values.extend(derived);
+        if config.target_position == TargetPosition::Last {
+            values.extend(targets);
+        }
+
+        TargetRow { window_id, values }
+    }
+
+    /// Output columns in file order with their dtypes.
+    pub fn columns(config: &Config) -> Vec<(String, &'static str)> {
+        let mut columns = vec![("window_id".to_string(), "int32")];
+        let targets = TargetRow::target_columns(config);
+        let features = config
+            .time_features
+            .iter()
+            .flat_map(|feature| feature.columns())
+            .chain(config.day_type_feature.then_some("day_type"))
+            .chain(config.slot_features.then_some(features::SLOT_COLUMNS).into_iter().flatten())
+            .map(String::from)
+            .chain(config.room_encoding.iter().flat_map(|encoding| encoding.columns()))
+            .chain(SENSOR_FIELDS.map(String::from))
+            // the inside temperature already is `temperature`
+            .chain(WEATHER_FIELDS.map(|name| if name == "temperature" { "outside_temperature" } else { name }).map(String::from))
+            .chain(features::derived_columns(config));
+        let names: Vec<String> = match config.target_position {
+            TargetPosition::First => targets.into_iter().chain(features).collect(),
+            TargetPosition::Last => features.chain(targets).collect(),
+        };
+        columns.extend(names.into_iter().map(|name| (name, "float32")));
+        columns
+    }
+
+    /// Names of the target columns, in `targets` order.
+    pub fn target_columns(config: &Config) -> Vec<String> {
+        config.targets.iter().map(|target| target.column(&config.occupancy_target)).collect()
+    }
+
+    pub fn header(config: &Config) -> Vec<String> {
+        TargetRow::columns(config).into_iter().map(|(name, _)| name).collect()
+    }
+}
+
+/// All windows of one location on one day. Days are what gets shuffled into
+/// folds, so windows of one day never end up on both sides of a split.
+#[derive(Debug, Clone)]
+pub struct LocationDay {
+    pub location: SensorLocation,
+    pub date: NaiveDate,
+    pub day_type: DayType,
+    /// Id of the first window, the rest are numbered consecutively.
+    pub first_window_id: i32,
+    pub windows: Vec<Vec<MinuteRecord>>,
+    /// Minutes after the last window, only read for forecast targets.
+    pub horizon: Vec<MinuteRecord>,
+}
+
+impl LocationDay {
+    /// Every minute covered by the windows of this day exactly once, windows
+    /// overlap so the same minute is part of many of them.
+    pub fn distinct_minutes(&self) -> impl Iterator<Item = &MinuteRecord> {
+        let mut seen = HashSet::new();
+        self.windows
+            .iter()
+            .flatten()
+            .filter(move |(ndt, ..)| seen.insert(*ndt))
+    }
+}
+
+/// All windows of one location on one day, flattened into output rows.
+#[derive(Debug, Clone)]
+pub struct DayRows {
+    pub location: SensorLocation,
+    pub date: NaiveDate,
+    pub rows: Vec<TargetRow>,
 }
 
 #[derive(Debug, Clone)]
 pub struct SensedPeople {
     sensor_location: SensorLocation,
     people: i32,
+    /// The room has a lesson in the timetable at this minute.
+    scheduled: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -141,7 +304,7 @@ impl SensedPeople {
                 Ok(p) => p,
                 Err(e) => return Err(format!("Error paring people for location: {:#?}", e).into()),
             },
-            None => return Err(format!("Error paring peope for location-missing:").into()),
+            None => return Err("Error paring peope for location-missing:".to_string().into()),
         };
         Ok(
             times
@@ -151,7 +314,8 @@ impl SensedPeople {
                         t,
                         SensedPeople {
                             sensor_location: sensor.clone(),
-                            people: people.clone(),
+                            people,
+                            scheduled: true,
                         }
                     )
                 })
@@ -181,7 +345,7 @@ fn parse_location_times(date: Option<&str>, time: Option<&str>) -> Result<Vec<Na
     };   
     let mut times = vec![];
     for i in 0..duration {
-        times.push(start_time.clone().add(Duration::minutes(i)))
+        times.push(start_time.add(Duration::minutes(i)))
     }
     Ok(times)
 }
@@ -196,17 +360,13 @@ fn get_slot_time(date: Option<&str>, time: Option<&str>) -> Result<(NaiveDateTim
         Some(t) => t,
         None => return Err("No time slot defined".into()),
     };
-    match t {
-        Ok(0)  => Ok((NaiveDateTime::parse_from_str(format!("{}T07:30:00Z", date).as_str(), "%Y-%m-%dT%H:%M:%S%Z")?, 30)),
-        Ok(1)  => Ok((NaiveDateTime::parse_from_str(format!("{}T08:00:00Z", date).as_str(), "%Y-%m-%dT%H:%M:%S%Z")?, 50)),
-        Ok(2)  => Ok((NaiveDateTime::parse_from_str(format!("{}T08:50:00Z", date).as_str(), "%Y-%m-%dT%H:%M:%S%Z")?, 50)),
-        Ok(3)  => Ok((NaiveDateTime::parse_from_str(format!("{}T09:40:00Z", date).as_str(), "%Y-%m-%dT%H:%M:%S%Z")?, 70)),
-        Ok(4)  => Ok((NaiveDateTime::parse_from_str(format!("{}T10:50:00Z", date).as_str(), "%Y-%m-%dT%H:%M:%S%Z")?, 50)),
-        Ok(5)  => Ok((NaiveDateTime::parse_from_str(format!("{}T11:40:00Z", date).as_str(), "%Y-%m-%dT%H:%M:%S%Z")?, 50)),
-        Ok(6)  => Ok((NaiveDateTime::parse_from_str(format!("{}T12:30:00Z", date).as_str(), "%Y-%m-%dT%H:%M:%S%Z")?, 50)),
-        Ok(7)  => Ok((NaiveDateTime::parse_from_str(format!("{}T13:20:00Z", date).as_str(), "%Y-%m-%dT%H:%M:%S%Z")?, 50)),
-        Ok(8)  => Ok((NaiveDateTime::parse_from_str(format!("{}T14:10:00Z", date).as_str(), "%Y-%m-%dT%H:%M:%S%Z")?, 45)),
-        _  => Err("error parsing start time of sime-slot".into()),
+    let slot = match t {
+        Ok(t) => usize::try_from(t).ok().and_then(timetable::slot_start),
+        Err(_) => None,
+    };
+    match slot {
+        Some((start, duration)) => Ok((NaiveDate::parse_from_str(date, "%Y-%m-%d")?.and_time(start), duration)),
+        None => Err("error parsing start time of sime-slot".into()),
     }
 }
 
@@ -221,10 +381,7 @@ impl SensorData {
             Some(t) => NaiveDateTime::parse_from_str(t, "%Y-%m-%dT%H:%M:%S%Z"),
             None => return Err("time parse err".to_string().into()),
         };
-        let time = match time_result {
-            Ok(t) => t,
-            Err(e) => return Err(e.into()),
-        };
+        let time = time_result?;
 
         let field = match field {
             Some(t) => String::from(t),
@@ -309,7 +466,7 @@ fn parse_sensor_data(reader: Reader<File>) -> Result<DashMap<NaiveDateTime, Vec<
                 return;
             },
         };
-        let mut sensors = data.entry(row.0).or_insert_with(Vec::new);
+        let mut sensors = data.entry(row.0).or_default();
         let mut existing_sensor = sensors.iter_mut().find(|s| s.location == row.1.sensor_location);
 
         if existing_sensor.is_none() {
@@ -323,6 +480,7 @@ fn parse_sensor_data(reader: Reader<File>) -> Result<DashMap<NaiveDateTime, Vec<
                 rh: None,
                 temperature: None,
                 vec_eq_co2: None,
+                derived: vec![],
             });
             existing_sensor = sensors.last_mut();
         }
@@ -345,7 +503,7 @@ fn parse_sensor_data(reader: Reader<File>) -> Result<DashMap<NaiveDateTime, Vec<
 }
 
 fn parse_weather_data(reader: Reader<File>) -> Result<DashMap<NaiveDateTime, WeatherPoint>, Box<dyn Error>> {
-    let mut records = reader.into_records();
+    let records = reader.into_records();
     let data: DashMap<NaiveDateTime, WeatherPoint> = DashMap::new();
     let mut prev_record: Option<(NaiveDateTime, WeatherPoint)> = None;
 
@@ -372,7 +530,7 @@ fn parse_weather_data(reader: Reader<File>) -> Result<DashMap<NaiveDateTime, Wea
             for i in 1..minutes {
                 let fraction = i as f32 / minutes as f32;
                 let interp_time = *prev_time + chrono::Duration::minutes(i);
-                let interp_point = interpolate_weather_points(&prev_data, &weather_point, fraction);
+                let interp_point = interpolate_weather_points(prev_data, &weather_point, fraction);
                 data.insert(interp_time, interp_point);
             }
         }
@@ -402,19 +560,21 @@ fn interpolate_weather_points(a: &WeatherPoint, b: &WeatherPoint, fraction: f32)
 fn parse_location_data(reader: Reader<File>) -> Result<DashMap<NaiveDateTime, Vec<SensedPeople>>, Box<dyn Error>> {
     let data = DashMap::new();
     
-    reader
+    // parsed in parallel but inserted in file order, so duplicates of a
+    // minute keep the order of their rows, see `occupancy::resolve_conflicts`
+    let rows = reader
         .into_records()
         .collect::<Vec<_>>()
         .into_par_iter()
-        .for_each(|r| {
+        .map(|r| {
             let row_record = match r {
                 Ok(row) => row,
                 Err(e) => {
                     println!("Something went wrong reading row: {:#?}", e);
-                    return;
+                    return vec![];
                 },
             };
-            let row = match SensedPeople::from(
+            match SensedPeople::from(
                 row_record.get(1),
                 row_record.get(3),
                 row_record.get(5),
@@ -423,16 +583,17 @@ fn parse_location_data(reader: Reader<File>) -> Result<DashMap<NaiveDateTime, Ve
                 Ok(r) => r,
                 Err(e) => {
                     println!("error parsing row: {:#?}", e);
-                    return;
+                    vec![]
                 },
-            };
-            
-            for (datetime, sensed_person) in row {
-                data.entry(datetime)
-                    .or_insert_with(Vec::new)
-                    .push(sensed_person);
             }
-        });
+        })
+        .collect::<Vec<_>>();
+
+    for (datetime, sensed_person) in rows.into_iter().flatten() {
+        data.entry(datetime)
+            .or_insert_with(Vec::new)
+            .push(sensed_person);
+    }
     
     Ok(data)
 }
@@ -447,8 +608,9 @@ fn merge_maps_updated(
     people_data: DashMap<NaiveDateTime, Vec<SensedPeople>>,
     sensor_data: DashMap<NaiveDateTime, Vec<Sensor>>,
     weather_data: DashMap<NaiveDateTime, WeatherPoint>,
-) -> DashMap<SensorLocation, Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>> {
-    let merged: DashMap<SensorLocation, Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>> = DashMap::new();
+    config: &Config,
+) -> DashMap<SensorLocation, Vec<MinuteRecord>> {
+    let merged: DashMap<SensorLocation, Vec<MinuteRecord>> = DashMap::new();
     
     for sensor_ref in sensor_data.iter() {
         let current_sensors_minute = sensor_ref.key();
@@ -458,19 +620,18 @@ fn merge_maps_updated(
             Some(d) => d.value().clone(),
             None => continue,
         };
-        if current_sensors_minute.hour() < 4 || current_sensors_minute.hour() >= 16 {
+        if current_sensors_minute.hour() < config.start_hour || current_sensors_minute.hour() >= config.end_hour {
             continue;
         }
 
+        let mut context = Vec::new();
+        features::push_context_values(current_sensors_minute_values, config, &mut context);
+
         for sensor in current_sensors_minute_values.iter() {
-            let current_minute_sensed_people = match current_minute_sensed_people_ref {
-                Some(ref p) => Some(p.value().clone()),
-                // will trigger for first sensor each minute
-                None => None,
-            };
+            let current_minute_sensed_people = current_minute_sensed_people_ref.as_ref().map(|p| p.value().clone());
 
             let current_minute_sensed_people_for_current_sensor = match current_minute_sensed_people {
-                Some(p) => p.iter().filter(|s| s.sensor_location == sensor.location).nth(0).cloned(),
+                Some(p) => p.iter().find(|s| s.sensor_location == sensor.location).cloned(),
                 None => None,
             };
 
@@ -479,159 +640,84 @@ fn merge_maps_updated(
                 None => SensedPeople {
                     sensor_location: sensor.location.clone(),
                     people: 0,
+                    scheduled: false,
                 },
             };
 
             let mut entry = merged
                 .entry(people.sensor_location.clone())
-                .or_insert_with(Vec::new);
+                .or_default();
+            let mut derived = sensor.derived.clone();
+            derived.extend(context.iter());
             // Add the merged record to the entry
             entry.push((
                 *current_sensors_minute, // time
                 sensor.clone(), // sensor data
                 people, // people data
                 current_weather_data.clone(),
+                derived, // derived features
             ));
         }
     }
     merged
 }
-fn merge_maps(
-    people_data: DashMap<NaiveDateTime, Vec<SensedPeople>>,
-    sensor_data: DashMap<NaiveDateTime, Vec<Sensor>>,
-) -> DashMap<SensorLocation, Vec<(NaiveDateTime, Sensor, SensedPeople)>> {
-    let merged: DashMap<SensorLocation, Vec<(NaiveDateTime, Sensor, SensedPeople)>> = DashMap::new();
-
-    // For each minute in the sensor data (not sorted)
-    for sensor_ref_multi in sensor_data.iter() {
-        // For each sensor record in the current minute
-        for sensor in sensor_ref_multi.value() {
-            // Get all people records for this minute (might not exist)
-            let people_ref_multi = people_data.get(sensor_ref_multi.key());
-            // Save the time
-            let time = sensor_ref_multi.key();
-            // Only collect data from 4am to 4pm
-            if time.hour() < START_HOUR || time.hour() >= END_HOUR {
-                continue;
-            }
-            // If there are any people recorded for this sensor at this minute
-            if let Some(people_ref_multi) = &people_ref_multi {
-                // We'll keep track of whether we've found a SensedPeople struct for the current location
-                let mut people_present = false;
-                // Go through all the sensed people records
-                for sensed_people in people_ref_multi.value() {
-                    // If the recording we're looking at matches with the current sensor we're looking at
-                    if sensor.location == sensed_people.sensor_location {
-                        // Get the target slot in merged hashmap to insert the values
-                        // Insert empty vector into merged (if it doesn't already exist) with sensor location as the key
-                        let mut entry = merged
-                            .entry(sensed_people.sensor_location.clone())
-                            .or_insert_with(Vec::new);
-                        // Add the merged record to the entry
-                        entry.push((
-                            *sensor_ref_multi.key(), // time
-                            sensor.clone(), // sensor data
-                            sensed_people.clone(), // people data
-                        ));
-                        // We've found a SensedPeople struct for this location
-                        people_present = true;
-                    }
-                }
-                // If no SensedPeople struct was found for this location, create one with a count of 0
-                if !people_present {
-                    // Get the target slot in merged hashmap to insert the values
-                    // Insert empty vector into merged (if it doesn't already exist) with sensor location as the key
-                    let mut entry = merged
-                        .entry(sensor.location.clone())
-                        .or_insert_with(Vec::new);
-                    // Add the merged record to the entry, creating a new SensedPeople struct with a count of 0
-                    entry.push((
-                        *sensor_ref_multi.key(),
-                        sensor.clone(),
-                        SensedPeople {
-                            sensor_location: sensor.location.clone(),
-                            people: 0,
-                        },
-                    ));
-                }
-            } else {
-                // If there are no matching people data points, add a new SensedPeople struct with a count of 0
-                // Get the target slot in merged hashmap to insert the values
-                // Insert empty vector into merged (if it doesn't already exist) with sensor location as the key
-                let mut entry = merged
-                    .entry(sensor.location.clone())
-                    .or_insert_with(Vec::new);
-                // Add the merged record to the entry, creating a new SensedPeople struct with a count of 0
-                entry.push((
-                    *sensor_ref_multi.key(),
-                    sensor.clone(),
-                    SensedPeople {
-                        sensor_location: sensor.location.clone(),
-                        people: 0,
-                    },
-                ));
-            }
-        }
-    }
-
-    merged
-}
-
 
 fn get_sorted_data_for_location(
-    data: &DashMap<SensorLocation, Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>>,
+    data: &DashMap<SensorLocation, Vec<MinuteRecord>>,
     location: &SensorLocation,
-) -> Option<Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>> {
+) -> Option<Vec<MinuteRecord>> {
     data.get(location).map(|multi_ref| {
         let mut sorted_data = multi_ref.value().clone();
-        sorted_data.sort_unstable_by_key(|(date, _, _, _)| *date);
+        sorted_data.sort_unstable_by_key(|(date, ..)| *date);
         sorted_data
     })
 }
 
 fn aggregate_by_date(
-    data: Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>,
-) -> HashMap<NaiveDate, Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>> {
-    let mut aggregated: HashMap<NaiveDate, Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>> = HashMap::new();
+    data: Vec<MinuteRecord>,
+) -> HashMap<NaiveDate, Vec<MinuteRecord>> {
+    let mut aggregated: HashMap<NaiveDate, Vec<MinuteRecord>> = HashMap::new();
     
     for tuple in data {
         let date = tuple.0.date();  // extract the date part of the NaiveDateTime
-        aggregated.entry(date).or_insert_with(Vec::new).push(tuple);
+        aggregated.entry(date).or_default().push(tuple);
     }
 
     aggregated
 }
 
 fn find_gaps(
-    data: &HashMap<NaiveDate, Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>>,
-) -> HashMap<NaiveDate, Vec<(NaiveTime, NaiveTime)>> {
-    let mut gaps: HashMap<NaiveDate, Vec<(NaiveTime, NaiveTime)>> = HashMap::new();
+    data: &HashMap<NaiveDate, Vec<MinuteRecord>>,
+    config: &Config,
+) -> HashMap<NaiveDate, Vec<(NaiveDateTime, NaiveDateTime)>> {
+    let mut gaps: HashMap<NaiveDate, Vec<(NaiveDateTime, NaiveDateTime)>> = HashMap::new();
     
-    let start_time = NaiveTime::from_hms(4, 0, 0);  // start of day at 4am
-    let end_time = NaiveTime::from_hms(16, 0, 0);  // end of day at 4pm
     let duration = Duration::minutes(1);  // each time slot is 1 minute
 
     for (&date, tuples) in data {
-        let mut last_time = start_time;
+        // start of day at 4am, end at 4pm; an end_hour of 24 is the next midnight
+        let midnight = date.and_time(NaiveTime::MIN);
+        let mut last_time = midnight + Duration::hours(config.start_hour as i64);
+        let end_time = midnight + Duration::hours(config.end_hour as i64);
         
-        for &(time, _, _, _) in tuples {
+        for &(time, ..) in tuples {
             let expected_time = last_time + duration;
             
             // Check if a gap exists between the expected time and the actual time
-            if time.time() > expected_time {
+            if time > expected_time {
                 // A gap exists
                 gaps.entry(date)
-                    .or_insert_with(Vec::new)
-                    .push((last_time, time.time()));
+                    .or_default()
+                    .push((last_time, time));
             }
 
-            last_time = time.time();
+            last_time = time;
         }
 
         // Check for a gap between the last recorded time and the end of day
         if last_time < end_time {
             gaps.entry(date)
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push((last_time, end_time));
         }
     }
@@ -640,9 +726,10 @@ fn find_gaps(
 }
 
 fn filter_days_by_gaps(
-    mut days: HashMap<NaiveDate, Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>>, 
-) -> HashMap<NaiveDate, Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>> {
-    let gaps = find_gaps(&days);
+    mut days: HashMap<NaiveDate, Vec<MinuteRecord>>, 
+    config: &Config,
+) -> HashMap<NaiveDate, Vec<MinuteRecord>> {
+    let gaps = find_gaps(&days, config);
     for (date, gaps_day) in gaps.iter() {
         // println!("Day: {} - gaps {:#?}", date, gaps_day);
         let big_gaps = gaps_day
@@ -656,28 +743,52 @@ fn filter_days_by_gaps(
     days
 }
 
+/// Windows of `window_size` minutes, the last one ending `horizon` minutes
+/// before the day does so every target has its future minute. With a
+/// horizon the minutes after the last window are kept next to the windows.
 fn generate_windows(
-    data: &HashMap<NaiveDate, Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>>, 
-    window_size: usize
-) -> HashMap<NaiveDate, Vec<Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>>> {
-    let mut windowed_data: HashMap<NaiveDate, Vec<Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>>> = HashMap::new();
+    data: &HashMap<NaiveDate, Vec<MinuteRecord>>, 
+    window_size: usize,
+    horizon: usize,
+) -> DayWindows {
+    let mut windowed_data: DayWindows = HashMap::new();
 
     for (&date, tuples) in data {
         let mut windows = Vec::new();
-        for i in 0..(tuples.len() - window_size) {
+        let count = tuples.len().saturating_sub(window_size + horizon);
+        for i in 0..count {
             windows.push(tuples[i..(i + window_size)].to_vec());
         }
-        windowed_data.insert(date, windows);
+        let after = match (count, horizon) {
+            (0, _) | (_, 0) => vec![],
+            _ => tuples[(count + window_size - 1)..].to_vec(),
+        };
+        windowed_data.insert(date, (windows, after));
     }
 
     windowed_data
 }
 
+/// Drops the days whose type is in `excluded_day_types`.
+fn filter_days_by_calendar(
+    mut days: HashMap<NaiveDate, Vec<MinuteRecord>>,
+    calendar: &Calendar,
+    config: &Config,
+) -> HashMap<NaiveDate, Vec<MinuteRecord>> {
+    days.retain(|date, _| !config.excluded_day_types.contains(&calendar.day_type(*date)));
+    days
+}
+
 fn structure_data(
-    merged_data: DashMap<SensorLocation, Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>>
-) ->  HashMap<SensorLocation, HashMap<NaiveDate, Vec<Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>>>> {
+    merged_data: DashMap<SensorLocation, Vec<MinuteRecord>>,
+    calendar: &Calendar,
+    scalers: Option<&FittedScalers>,
+    levels: &BaselineLevels,
+    config: &Config,
+) -> (HashMap<SensorLocation, DayWindows>, Vec<VentilationEvent>) {
     // define a hashmap to hold all the data
-    let mut data: HashMap<SensorLocation, HashMap<NaiveDate, Vec<Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>>>> = HashMap::new();
+    let mut data: HashMap<SensorLocation, DayWindows> = HashMap::new();
+    let mut events = Vec::new();
 
     // loop over all locations
     for ref_location in merged_data.iter() {
@@ -693,413 +804,591 @@ fn structure_data(
 
         // aggregate, filter and generate windows for the data
         let location_data = aggregate_by_date(location_data);
-        let location_data = filter_days_by_gaps(location_data);
-        let location_data = generate_windows(&location_data, WINDOW_SIZE);
+        let location_data = filter_days_by_calendar(location_data, calendar, config);
+        let location_data = filter_days_by_gaps(location_data, config);
+        let (location_data, location_events) = detect_events(location_data, location, scalers, levels, config);
+        events.extend(location_events);
+        let location_data = series::add_series_features(location_data, config);
+        let location_data = generate_windows(&location_data, config.window_size, config.max_horizon());
 
         // store the windowed data in the hashmap
         data.insert(location.clone(), location_data);
     }
-    data
+    (data, events)
 }
 
-fn restructure_data_to_output(
-    data: HashMap<SensorLocation, HashMap<NaiveDate, Vec<Vec<(NaiveDateTime, Sensor, SensedPeople, WeatherPoint)>>>>,
-) -> Vec<Vec<TargetRow>> {
+/// Sorts the windowed data by location and day and numbers the windows, so
+/// window ids and fold contents are reproducible between runs.
+fn collect_location_days(data: HashMap<SensorLocation, DayWindows>, calendar: &Calendar) -> Vec<LocationDay> {
     let mut window_id = 1;
-    let mut result: Vec<Vec<TargetRow>> = Vec::new();
-
-    for (_, date_map) in data {
-        for (date, windows) in date_map {
-            let mut date_rows: Vec<TargetRow> = Vec::new();
-
-            for (_, window) in windows.into_iter().enumerate() {
-                let window_rows: Vec<TargetRow> = window
-                    .into_iter()
-                    .map(|(ndt, sensor, sensed_people, weather)| {
-                        TargetRow {
-                            window_id: window_id as i32,
-                            jan: if date.month() == 1 {1.} else {0.},
-                            feb: if date.month() == 2 {1.} else {0.},
-                            mar: if date.month() == 3 {1.} else {0.},
-                            apr: if date.month() == 4 {1.} else {0.},
-                            may: if date.month() == 5 {1.} else {0.},
-                            jun: if date.month() == 6 {1.} else {0.},
-                            jul: if date.month() == 7 {1.} else {0.},
-                            aug: if date.month() == 8 {1.} else {0.},
-                            sep: if date.month() == 9 {1.} else {0.},
-                            oct: if date.month() == 10 {1.} else {0.},
-                            nov: if date.month() == 11 {1.} else {0.},
-                            dec: if date.month() == 12 {1.} else {0.},
-                            day: date.day() as f32,
-                            time: (ndt.num_seconds_from_midnight() / 60) as f32,
-                            dew_point: sensor.dew_point.unwrap_or_default(),
-                            luminance: sensor.luminance.unwrap_or_default(),
-                            voc_index: sensor.voc_index.unwrap_or_default(),
-                            co2: sensor.co2.unwrap_or_default(),
-                            abs_humidity: sensor.abs_humidity.unwrap_or_default(),
-                            rh: sensor.rh.unwrap_or_default(),
-                            temperature: sensor.temperature.unwrap_or_default(),
-                            vec_eq_co2: sensor.vec_eq_co2.unwrap_or_default(),
-                            outside_temperature: weather.temperature,
-                            avg_temperature: weather.avg_temperature,
-                            min_temperature: weather.min_temperature,
-                            max_temperature: weather.max_temperature,
-                            rel_humidity: weather.rel_humidity,
-                            avg_rel_humidity: weather.avg_rel_humidity,
-                            min_rel_humidity: weather.min_rel_humidity,
-                            max_rel_humidity: weather.max_rel_humidity,
-                            precipitation: weather.precipitation,
-                            wind_speed: weather.wind_speed,
-                            people: sensed_people.people as f32,
-                        }
-                    })
-                    .collect();
-                window_id += 1;
-                date_rows.extend(window_rows);
-            }
+    let mut result: Vec<LocationDay> = Vec::new();
+
+    let mut data: Vec<(SensorLocation, DayWindows)> = data.into_iter().collect();
+    data.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    for (location, date_map) in data {
+        let mut date_map: Vec<_> = date_map.into_iter().collect();
+        date_map.sort_by_key(|(date, _)| *date);
+
+        for (date, (windows, horizon)) in date_map {
+            let first_window_id = window_id;
+            window_id += windows.len() as i32;
+            result.push(LocationDay {
+                location: location.clone(),
+                date,
+                day_type: calendar.day_type(date),
+                first_window_id,
+                windows,
+                horizon,
+            });
+        }
+    }
+    result
+}
 
-            result.push(date_rows);
+fn restructure_data_to_output(
+    data: Vec<LocationDay>,
+    config: &Config,
+) -> Vec<DayRows> {
+    let mut result: Vec<DayRows> = Vec::new();
+
+    for day in data {
+        let mut date_rows: Vec<TargetRow> = Vec::new();
+        let minutes: BTreeMap<NaiveDateTime, &MinuteRecord> =
+            day.distinct_minutes().chain(day.horizon.iter()).map(|record| (record.0, record)).collect();
+
+        for (window_index, window) in day.windows.iter().enumerate() {
+            let window_id = day.first_window_id + window_index as i32;
+            date_rows.extend(window.iter().map(|record| TargetRow::new(window_id, record, &day, &minutes, config)));
         }
+
+        result.push(DayRows {
+            location: day.location,
+            date: day.date,
+            rows: date_rows,
+        });
     }
 
     result // Collect all the data into a single vector
 }
 
 
-fn shuffle_and_split_into_folds(mut data: Vec<Vec<TargetRow>>, folds: i32) -> Vec<Vec<Vec<TargetRow>>> {
+fn shuffle_and_split_into_folds<T>(mut data: Vec<T>, folds: usize, seed: u8) -> Vec<Vec<T>> {
     // Create a mutable reference to data and shuffle it
-    let mut rng = StdRng::from_seed(SEED);
+    let mut rng = StdRng::from_seed([seed; 32]);
     data.shuffle(&mut rng);
 
     // Calculate the size of each fold
-    let fold_size = data.len() / folds as usize;
+    let fold_size = data.len() / folds;
 
     // Create the resulting vector of folds
     let mut result = Vec::new();
+    let mut data = data.into_iter();
 
-    // Create each fold by slicing the shuffled data
+    // Create each fold by taking the next chunk of the shuffled data
     for i in 0..folds {
-        let start_index = (i as usize) * fold_size;
-        let end_index = if i == folds - 1 {
+        let take = if i == folds - 1 {
             data.len() // If this is the last fold, take all the remaining data
         } else {
-            start_index + fold_size
+            fold_size
         };
 
         // Add the fold to the result
-        result.push(data[start_index..end_index].to_vec());
+        result.push(data.by_ref().take(take).collect());
     }
 
     result
 }
 
-fn export_fold(data: Vec<TargetRow>, file: File) -> std::io::Result<()> {
-    let mut writer = csv::Writer::from_writer(file);
+#[derive(Debug, Serialize)]
+struct Split {
+    split: usize,
+    train: Vec<String>,
+    test: Vec<String>,
+    /// Scalers the rows of this split were scaled with.
+    scalers: String,
+}
+
+fn fold_file_name(fold_index: usize) -> String {
+    format!("fold_{}.csv", fold_index + 1)
+}
+
+/// Every fold is the test set of exactly one split and part of the training
+/// set of all the others.
+fn build_splits(num_of_folds: usize) -> Vec<Split> {
+    (0..num_of_folds)
+        .map(|test_index| Split {
+            split: test_index + 1,
+            train: (0..num_of_folds)
+                .filter(|i| *i != test_index)
+                .map(fold_file_name)
+                .collect(),
+            test: vec![fold_file_name(test_index)],
+            scalers: "scalers.json".to_string(),
+        })
+        .collect()
+}
+
+/// Splits whose train and test sets were written to their own `fold_k` directory.
+fn build_materialized_splits(num_of_folds: usize) -> Vec<Split> {
+    (0..num_of_folds)
+        .map(|test_index| Split {
+            split: test_index + 1,
+            train: vec![format!("fold_{}/train.csv", test_index + 1)],
+            test: vec![format!("fold_{}/test.csv", test_index + 1)],
+            scalers: format!("fold_{}/scalers.json", test_index + 1),
+        })
+        .collect()
+}
+
+/// Where a window comes from, one row of `windows.csv`.
+#[derive(Debug, Serialize)]
+struct WindowInfo<'a> {
+    window_id: i32,
+    fold: usize,
+    location: &'a SensorLocation,
+    room_index: usize,
+    date: NaiveDate,
+    day_type: DayType,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+}
+
+/// Writes `windows.csv`, the room, day and time span of every window keyed
+/// by `window_id`, for joining predictions back and per room metrics.
+fn export_window_table(folded_data: &[Vec<LocationDay>], out_dir: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut writer = csv::Writer::from_writer(File::create(out_dir.join("windows.csv"))?);
+    for (fold_index, fold) in folded_data.iter().enumerate() {
+        for day in fold {
+            for (window_index, window) in day.windows.iter().enumerate() {
+                let (Some((start, ..)), Some((end, ..))) = (window.first(), window.last()) else {
+                    continue;
+                };
+                writer.serialize(WindowInfo {
+                    window_id: day.first_window_id + window_index as i32,
+                    fold: fold_index + 1,
+                    location: &day.location,
+                    room_index: day.location.index(),
+                    date: day.date,
+                    day_type: day.day_type,
+                    start: *start,
+                    end: *end,
+                })?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn export_fold<R: Serialize>(header: &[String], data: impl Iterator<Item = R>, file: File) -> csv::Result<()> {
+    let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(file);
+    writer.write_record(header)?;
 
     for row in data {
         writer.serialize(row)?;
     }
 
-    writer.flush()
+    Ok(writer.flush()?)
 }
 
-fn export_data(folded_data: Vec<Vec<Vec<TargetRow>>>) -> Result<(), Box<String>> {
-    let dash_data: DashMap<usize, Vec<Vec<TargetRow>>> = DashMap::new();
-    let num_of_folds = folded_data.len();
-    for (i, fold) in folded_data.into_iter().enumerate() {
-        dash_data.insert(i, fold);
+fn export_data(folded_data: &[Vec<DayRows>], out_dir: &Path, config: &Config) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let header = TargetRow::header(config);
+    // each fold is written exactly once, splits only reference the files
+    folded_data
+        .par_iter()
+        .enumerate()
+        .try_for_each(|(fold_index, fold)| -> Result<(), Box<dyn Error + Send + Sync>> {
+            let fold_file = out_dir.join(fold_file_name(fold_index));
+            println!("Writing fold {}", fold_file.display());
+            export_fold(&header, fold.iter().flat_map(|day| day.rows.iter()), File::create(&fold_file)?)?;
+            Ok(())
+        })?;
+
+    let splits = build_splits(folded_data.len());
+    serde_json::to_writer_pretty(File::create(out_dir.join("splits.json"))?, &splits)?;
+
+    if config.materialize_splits {
+        materialize_splits(folded_data, out_dir, &header)?;
     }
+    Ok(())
+}
 
-    (0..num_of_folds)
-        .collect::<Vec<usize>>()
+/// Legacy layout: `fold_k/test.csv` holds fold k and `fold_k/train.csv` the
+/// concatenation of all the other folds.
+fn materialize_splits(
+    folded_data: &[Vec<DayRows>],
+    out_dir: &Path,
+    header: &[String],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    (0..folded_data.len())
         .into_par_iter()
-        .enumerate()
-        .try_for_each(|(fold_index, _data)| {
-            // Create fold directory
-            let fold_dir = format!("out/fold_{}", fold_index + 1);
-            println!("Constructing: {}", fold_dir);
-            if let Err(e) = fs::create_dir_all(&fold_dir) {
-                return Err(Box::new(e.to_string()));
-            };
-            
-            let mut training_data: Vec<Vec<TargetRow>> = Vec::new();
-
-            for i in 0..num_of_folds {
-                if i == fold_index {
-                    let value = {
-
-                        let test_data = match dash_data.get(&i) {
-                            Some(d) => d,
-                            None => todo!(),
-                        };
-                        test_data.value().clone()
-                    };
-
-                    println!("Writing test data {}", fold_dir);
-                    // if let Err(e) = pickle::to_writer(&mut test_file, &value, SerOptions::default()) {
-                    //     return Err(Box::new(e.to_string()));
-                    // };
-                    let test_file = match fs::File::create(Path::new(&fold_dir).join("test.csv")) {
-                        Ok(d) => d,
-                        Err(e) =>  return Err(Box::new(e.to_string())),
-                    };
-                    if let Err(e) = export_fold(
-                        value.into_iter().flatten().collect::<Vec<TargetRow>>(), 
-                        test_file
-                    ) {
-                        println!("Error saving test data {}: {}", fold_dir, e.to_string());
-                    };
-                } else {
-                    let target_fold = (fold_index + i) % num_of_folds;
-                    match dash_data.get(&i) {
-                        Some(d) => training_data.extend_from_slice(d.value()),
-                        None => return Err(Box::new(format!("data not found {} {}", target_fold, fold_index))),
-                    }
-                }
-            }
-            println!("Writing train data {}", fold_dir);
-            // if let Err(e) = pickle::to_writer(&mut train_file, &training_data, SerOptions::default()) {
-            //     return Err(Box::new(e.to_string()));
-            // };
-            let train_file = match fs::File::create(Path::new(&fold_dir).join("train.csv")) {
-                Ok(d) => d,
-                Err(e) =>  return Err(Box::new(e.to_string())),
-            };
-
-            if let Err(e) = export_fold(
-                training_data.into_iter().flatten().collect::<Vec<TargetRow>>(), 
-                train_file
-            ) {
-                println!("Error saving test data {}: {}", fold_dir, e.to_string());
-            };
+        .try_for_each(|fold_index| -> Result<(), Box<dyn Error + Send + Sync>> {
+            let fold_dir = out_dir.join(format!("fold_{}", fold_index + 1));
+            println!("Constructing: {}", fold_dir.display());
+            fs::create_dir_all(&fold_dir)?;
+
+            println!("Writing test data {}", fold_dir.display());
+            export_fold(
+                header,
+                folded_data[fold_index].iter().flat_map(|day| day.rows.iter()),
+                File::create(fold_dir.join("test.csv"))?,
+            )?;
+
+            println!("Writing train data {}", fold_dir.display());
+            let training_data = folded_data
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != fold_index)
+                .flat_map(|(_, fold)| fold.iter().flat_map(|day| day.rows.iter()));
+            export_fold(header, training_data, File::create(fold_dir.join("train.csv"))?)?;
 
             Ok(())
         })
 }
 
-pub fn scale_sensor_data(data: &DashMap<NaiveDateTime, Vec<Sensor>>) -> DashMap<NaiveDateTime, Vec<Sensor>> {
-    let mut scaled_data = DashMap::new();
-    
-    let sensors: Vec<Sensor> = data.iter().flat_map(|item| item.value().clone()).collect();
-
-    let dew_point_scaler = RobustScaler::new(
-        sensors.iter().filter_map(|sensor| sensor.dew_point).collect::<Vec<_>>().as_slice()
-    );
-    let luminance_scaler = RobustScaler::new(
-        sensors.iter().filter_map(|sensor| sensor.luminance).collect::<Vec<_>>().as_slice()
-    );
-    let voc_index_scaler = RobustScaler::new(
-        sensors.iter().filter_map(|sensor| sensor.voc_index).collect::<Vec<_>>().as_slice()
-    );
-    let co2_scaler = RobustScaler::new(
-        sensors.iter().filter_map(|sensor| sensor.co2).collect::<Vec<_>>().as_slice()
-    );
-    let abs_humidity_scaler = RobustScaler::new(
-        sensors.iter().filter_map(|sensor| sensor.abs_humidity).collect::<Vec<_>>().as_slice()
-    );
-    let temperature_scaler = RobustScaler::new(
-        sensors.iter().filter_map(|sensor| sensor.temperature).collect::<Vec<_>>().as_slice()
-    );
-    let vec_eq_co2_scaler = RobustScaler::new(
-        sensors.iter().filter_map(|sensor| sensor.vec_eq_co2).collect::<Vec<_>>().as_slice()
-    );
-
-    for item in data.iter() {
-        let date_time = item.key().clone();
-        let sensors = item.value().clone();
-
-        let scaled_sensors = sensors.into_iter().map(|sensor| {
-            Sensor {
-                location: sensor.location.clone(),
-                dew_point: sensor.dew_point.map(|value| dew_point_scaler.transform(value)),
-                luminance: sensor.luminance.map(|value| luminance_scaler.transform(value)),
-                voc_index: sensor.voc_index.map(|value| voc_index_scaler.transform(value)),
-                co2: sensor.co2.map(|value| co2_scaler.transform(value)),
-                abs_humidity: sensor.abs_humidity.map(|value| abs_humidity_scaler.transform(value)),
-                rh: sensor.rh,  // Not scaling RH as it's already bounded between 0 and 100
-                temperature: sensor.temperature.map(|value| temperature_scaler.transform(value)),
-                vec_eq_co2: sensor.vec_eq_co2.map(|value| vec_eq_co2_scaler.transform(value)),
-            }
-        }).collect();
-
-        scaled_data.insert(date_time, scaled_sensors);
-    }
-    
-    scaled_data
+fn scaled_rows<'a>(
+    days: impl Iterator<Item = &'a LocationDay> + 'a,
+    scalers: &'a FittedScalers,
+    config: &'a Config,
+) -> impl Iterator<Item = TargetRow> + 'a {
+    days.flat_map(move |day| restructure_data_to_output(vec![scale_location_day(day, scalers, config)], config))
+        .flat_map(|day| day.rows)
 }
 
-pub fn scale_weather_data(data: &DashMap<NaiveDateTime, WeatherPoint>) -> DashMap<NaiveDateTime, WeatherPoint> {
-    let mut scaled_data = DashMap::new();
-    
-    let temperature_scaler = RobustScaler::new(
-        data.iter().map(|w| w.temperature).collect::<Vec<_>>().as_slice()
-    );
-    let avg_temperature_scaler = RobustScaler::new(
-        data.iter().map(|w| w.avg_temperature).collect::<Vec<_>>().as_slice()
-    );
-    let min_temperature_scaler = RobustScaler::new(
-        data.iter().map(|w| w.min_temperature).collect::<Vec<_>>().as_slice()
-    );
-    let max_temperature_scaler = RobustScaler::new(
-        data.iter().map(|w| w.max_temperature).collect::<Vec<_>>().as_slice()
-    );
-    let rel_humidity_scaler = RobustScaler::new(
-        data.iter().map(|w| w.rel_humidity).collect::<Vec<_>>().as_slice()
-    );
-    let avg_rel_humidity_scaler = RobustScaler::new(
-        data.iter().map(|w| w.avg_rel_humidity).collect::<Vec<_>>().as_slice()
-    );
-    let min_rel_humidity_scaler = RobustScaler::new(
-        data.iter().map(|w| w.min_rel_humidity).collect::<Vec<_>>().as_slice()
-    );
-    let max_rel_humidity_scaler = RobustScaler::new(
-        data.iter().map(|w| w.max_rel_humidity).collect::<Vec<_>>().as_slice()
-    );
-    let precipitation_scaler = RobustScaler::new(
-        data.iter().map(|w| w.precipitation).collect::<Vec<_>>().as_slice()
-    );
-    let wind_speed_scaler = RobustScaler::new(
-        data.iter().map(|w| w.wind_speed).collect::<Vec<_>>().as_slice()
-    );
-    
-
-    for item in data.iter() {
+/// Fits the scalers of every split on its training days only and applies them
+/// to both sides, so no test statistics leak into the training features. The
+/// scaled data differs per split, so every split gets its own `fold_k`
+/// directory. Rows are produced one day at a time to keep memory bounded.
+fn export_scaled_splits(
+    folded_data: &[Vec<LocationDay>],
+    out_dir: &Path,
+    config: &Config,
+) -> Result<Vec<FittedScalers>, Box<dyn Error + Send + Sync>> {
+    let header = TargetRow::header(config);
+    let fitted = (0..folded_data.len())
+        .into_par_iter()
+        .map(|fold_index| -> Result<FittedScalers, Box<dyn Error + Send + Sync>> {
+            let fold_dir = out_dir.join(format!("fold_{}", fold_index + 1));
+            println!("Constructing: {}", fold_dir.display());
+            fs::create_dir_all(&fold_dir)?;
+
+            let training_days: Vec<&LocationDay> = folded_data
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != fold_index)
+                .flat_map(|(_, fold)| fold.iter())
+                .collect();
+            let scalers = fit_split_scalers(&training_days, config);
+            scalers.save(&fold_dir)?;
+
+            println!("Writing test data {}", fold_dir.display());
+            export_fold(
+                &header,
+                scaled_rows(folded_data[fold_index].iter(), &scalers, config),
+                File::create(fold_dir.join("test.csv"))?,
+            )?;
+
+            println!("Writing train data {}", fold_dir.display());
+            export_fold(
+                &header,
+                scaled_rows(training_days.into_iter(), &scalers, config),
+                File::create(fold_dir.join("train.csv"))?,
+            )?;
+
+            Ok(scalers)
+        })
+        .collect::<Result<Vec<FittedScalers>, _>>()?;
 
-        let date_time = item.key().clone();
-        let sensor = item.value().clone();
+    let splits = build_materialized_splits(folded_data.len());
+    serde_json::to_writer_pretty(File::create(out_dir.join("splits.json"))?, &splits)?;
 
-        
-        let scaled_sensor = WeatherPoint {
-            temperature: temperature_scaler.transform(sensor.temperature),
-            avg_temperature: avg_temperature_scaler.transform(sensor.avg_temperature),
-            min_temperature: min_temperature_scaler.transform(sensor.min_temperature),
-            max_temperature: max_temperature_scaler.transform(sensor.max_temperature),
-            rel_humidity: rel_humidity_scaler.transform(sensor.rel_humidity),
-            avg_rel_humidity: avg_rel_humidity_scaler.transform(sensor.avg_rel_humidity),
-            min_rel_humidity: min_rel_humidity_scaler.transform(sensor.min_rel_humidity),
-            max_rel_humidity: max_rel_humidity_scaler.transform(sensor.max_rel_humidity),
-            precipitation: precipitation_scaler.transform(sensor.precipitation),
-            wind_speed: wind_speed_scaler.transform(sensor.wind_speed),
-        };
-
-        scaled_data.insert(date_time, scaled_sensor);
-    }
-    
-    scaled_data
+    Ok(fitted)
 }
 
+/// Exits with a failure code when the dataset could not be written, so
+/// scripts can tell. Problems with the inputs panic.
+fn main() -> ExitCode {
+    let config = match Config::from_args() {
+        Ok(c) => c,
+        Err(e) => panic!("Something went wrong reading arguments: {:#?}", e),
+    };
+    let mut timings: BTreeMap<String, f64> = BTreeMap::new();
 
-
-fn main() {
-    
     let now = Instant::now();
-    let (sensor_reader_1, sensor_reader_2, location_data_reader, weather_data_reader_1, weather_data_reader_2) = get_readers();
-    let (sensor_data, location_data, weather_data) = get_data(sensor_reader_1, sensor_reader_2, location_data_reader, weather_data_reader_1, weather_data_reader_2);
+    let inputs = match manifest::hash_inputs(&config) {
+        Ok(i) => i,
+        Err(e) => panic!("Something went wrong hashing input files: {:#?}", e),
+    };
+    let provenance = match manifest::provenance(&config, &inputs) {
+        Ok(p) => p,
+        Err(e) => panic!("Something went wrong computing provenance: {:#?}", e),
+    };
+    let dataset_name = config.dataset_name.clone().unwrap_or_else(|| provenance[..16].to_string());
+    let dataset_dir = match DatasetDir::prepare(Path::new(&config.output_dir), &dataset_name, &provenance, config.force) {
+        Ok(Some(d)) => d,
+        Ok(None) => {
+            println!("Dataset {} is already up to date", Path::new(&config.output_dir).join(&dataset_name).display());
+            return ExitCode::SUCCESS;
+        },
+        Err(e) => {
+            println!("Error when preparing the output directory: {}", e);
+            return ExitCode::FAILURE;
+        },
+    };
+    let calendar = match Calendar::new(&config) {
+        Ok(c) => c,
+        Err(e) => panic!("Something went wrong reading the calendar: {:#?}", e),
+    };
+    let rooms = match Rooms::new(&config) {
+        Ok(r) => r,
+        Err(e) => panic!("Something went wrong reading the room metadata: {:#?}", e),
+    };
+    let (sensor_data, location_data, weather_data) = get_data(&config);
+    let conflicts = match resolve_conflicts(&location_data, config.occupancy_conflicts) {
+        Ok(c) => c,
+        Err(e) => panic!("Something went wrong resolving occupancy counts: {:#?}", e),
+    };
+    if let Err(e) = write_conflicts(&conflicts, &dataset_dir.staging) {
+        println!("Error when saving occupancy conflicts: {:#?}", e.to_string());
+    }
+    add_psychrometrics(&sensor_data, &weather_data, &config);
+    if let Some(mass_balance) = &config.mass_balance {
+        let estimates = estimate(&sensor_data, mass_balance, &rooms);
+        add_mass_balance(&sensor_data, &estimates, mass_balance);
+        if mass_balance.baseline_file {
+            if let Err(e) = write_baseline(&estimates, &location_data, &dataset_dir.staging, &config, mass_balance) {
+                println!("Error when saving mass balance baseline: {:#?}", e.to_string());
+            }
+        }
+    }
+    add_room_features(&sensor_data, &rooms, &config);
+    add_solar_features(&sensor_data, &config);
+    let (sensor_data, baseline_levels) = match &config.baseline {
+        Some(baseline) => {
+            let (sensor_data, levels) = subtract_baselines(&sensor_data, baseline);
+            if let Err(e) = write_baselines(&levels, &dataset_dir.staging) {
+                println!("Error when saving baselines: {:#?}", e.to_string());
+            }
+            (sensor_data, levels)
+        },
+        None => (sensor_data, BaselineLevels::new()),
+    };
 
-    let sensor_data = scale_sensor_data(&sensor_data);
-    let weather_data = scale_weather_data(&weather_data);
+    let (sensor_data, weather_data, global_scalers) = match config.scaling {
+        ScalingMode::Global => {
+            let scalers = match &config.scalers_file {
+                Some(path) => match FittedScalers::load(Path::new(path)) {
+                    Ok(s) => s,
+                    Err(e) => panic!("Something went wrong loading scalers: {:#?}", e),
+                },
+                None => fit_data_scalers(&sensor_data, &weather_data, &config),
+            };
+            if let Err(e) = scalers.save(&dataset_dir.staging) {
+                panic!("Something went wrong saving scalers: {:#?}", e);
+            }
+            (
+                scale_sensor_data(sensor_data, &scalers, &config),
+                scale_weather_data(weather_data, &scalers.weather),
+                Some(scalers),
+            )
+        },
+        // fitted later, once the folds are known
+        ScalingMode::PerSplit => (sensor_data, weather_data, None),
+    };
 
     let elapsed = now.elapsed();
     println!("Parsing from file: {:.2?}", elapsed);
+    timings.insert("parse".to_string(), elapsed.as_secs_f64());
     let resturcture = Instant::now();
 
-    let data = merge_maps_updated(location_data, sensor_data, weather_data);
-    data.remove(&SensorLocation::Jedilnica);
-    data.remove(&SensorLocation::Hodnik);
-    data.remove(&SensorLocation::Zbornica);
+    let data = merge_maps_updated(location_data, sensor_data, weather_data, &config);
+    for location in config.excluded_locations.iter() {
+        data.remove(location);
+    }
 
-    let data = structure_data(data);
-    let data = restructure_data_to_output(data);
+    let (data, mut events) = structure_data(data, &calendar, global_scalers.as_ref(), &baseline_levels, &config);
+    if config.ventilation.is_some() {
+        if let Err(e) = write_events(&mut events, &dataset_dir.staging) {
+            println!("Error when saving ventilation events: {:#?}", e.to_string());
+        }
+    }
+    let data = collect_location_days(data, &calendar);
     
-    let data: Vec<Vec<Vec<TargetRow>>> = shuffle_and_split_into_folds(data, FOLDS); 
+    let data: Vec<Vec<LocationDay>> = shuffle_and_split_into_folds(data, config.folds, config.seed); 
+    let fold_summaries = manifest::summarize_folds(&data, &config);
+    manifest::print_class_balance(&fold_summaries);
+    if let Err(e) = export_window_table(&data, &dataset_dir.staging) {
+        println!("Error when saving window table: {:#?}", e.to_string());
+        return ExitCode::FAILURE;
+    }
     
     let elapsed = resturcture.elapsed();
     println!("Resturcture: {:.2?}", elapsed);
+    timings.insert("restructure".to_string(), elapsed.as_secs_f64());
     let export = Instant::now();
 
-
-    if let Err(e) = export_data(data) {
-        println!("Error when saving folded data: {:#?}", e.to_string());
-    }
+    let split_scalers = match config.scaling {
+        ScalingMode::Global => {
+            let data: Vec<Vec<DayRows>> = data.into_iter().map(|fold| restructure_data_to_output(fold, &config)).collect();
+            export_data(&data, &dataset_dir.staging, &config).map(|_| vec![])
+        },
+        ScalingMode::PerSplit => export_scaled_splits(&data, &dataset_dir.staging, &config),
+    };
+    let split_scalers = match split_scalers {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Error when saving folded data: {:#?}", e.to_string());
+            return ExitCode::FAILURE;
+        },
+    };
 
     let elapsed = export.elapsed();
     println!("Export: {:.2?}", elapsed);
+    timings.insert("export".to_string(), elapsed.as_secs_f64());
     let elapsed = now.elapsed();
     println!("Total: {:.2?}", elapsed);
-}
-
-fn get_readers() -> (Reader<File>, Reader<File>, Reader<File>, Reader<File>, Reader<File>) {
-    let sensor_reader_1 = match read_csv("data/jan_feb_mar_ajdovscina_iaq.csv") {
-        Ok(r) => r,
-        Err(e) => panic!("Something went worng reading csv: {:#?}", e),
-    };
-
-    let sensor_reader_2 = match read_csv("data/apr_maj_jun_ajdovscina_iaq.csv") {
-        Ok(r) => r,
-        Err(e) => panic!("Something went worng reading csv: {:#?}", e),
-    };
-
-    let location_data_reader = match read_csv("data/school_data.csv") {
-        Ok(r) => r,
-        Err(e) => panic!("Something went worng reading csv: {:#?}", e),
-    };
+    timings.insert("total".to_string(), elapsed.as_secs_f64());
 
-    let weather_data_reader_1 = match read_csv("data/vreme_jan_feb_mar.csv") {
-        Ok(r) => r,
-        Err(e) => panic!("Something went worng reading csv: {:#?}", e),
-    };
+    let manifest = Manifest::new(&config, provenance, inputs, global_scalers, split_scalers, fold_summaries, timings);
+    if let Err(e) = manifest.write(&dataset_dir.staging) {
+        println!("Error when saving manifest: {:#?}", e.to_string());
+        return ExitCode::FAILURE;
+    }
 
-    let weather_data_reader_2 = match read_csv("data/vreme_apr_maj_jun.csv") {
-        Ok(r) => r,
-        Err(e) => panic!("Something went worng reading csv: {:#?}", e),
-    };
-    (sensor_reader_1, sensor_reader_2, location_data_reader, weather_data_reader_1, weather_data_reader_2)
+    match dataset_dir.commit() {
+        Ok(dir) => {
+            println!("Dataset written to {}", dir.display());
+            ExitCode::SUCCESS
+        },
+        Err(e) => {
+            println!("Error when moving dataset into place: {:#?}", e.to_string());
+            ExitCode::FAILURE
+        },
+    }
 }
 
-fn get_data(
-    sensor_reader_1: Reader<File>, 
-    sensor_reader_2: Reader<File>, 
-    location_data_reader: Reader<File>, 
-    weather_data_reader_1: Reader<File>, 
-    weather_data_reader_2: Reader<File>,
-) -> (
-    DashMap<NaiveDateTime, Vec<Sensor>>,
-    DashMap<NaiveDateTime, Vec<SensedPeople>>,
-    DashMap<NaiveDateTime, WeatherPoint>
-) {
-    let weather_data = match parse_weather_data(weather_data_reader_1) {
-        Ok(r) => r,
-        Err(e) => panic!("Something went worng reading weather csv 1: {:#?}", e),
-    };
+fn get_data(config: &Config) -> ParsedData {
+    let weather_data = DashMap::new();
+    for file in config.weather_files.iter() {
+        let reader = match read_csv(file) {
+            Ok(r) => r,
+            Err(e) => panic!("Something went worng reading csv {}: {:#?}", file, e),
+        };
+        let data = match parse_weather_data(reader) {
+            Ok(r) => r,
+            Err(e) => panic!("Something went worng reading weather csv {}: {:#?}", file, e),
+        };
+        for val_ref in data.into_iter() {
+            weather_data.insert(val_ref.0, val_ref.1);
+        }
+    }
 
-    let weather_data_2 = match parse_weather_data(weather_data_reader_2) {
+    let location_data_reader = match read_csv(&config.school_file) {
         Ok(r) => r,
-        Err(e) => panic!("Something went worng reading weather csv 2: {:#?}", e),
+        Err(e) => panic!("Something went worng reading csv {}: {:#?}", config.school_file, e),
     };
-
-
     let location_data = match parse_location_data(location_data_reader) {
         Ok(r) => r,
         Err(e) => panic!("Something went worng reading location csv: {:#?}", e),
     };
+    if let Err(e) = occupancy::apply_sources(&location_data, config) {
+        panic!("Something went wrong reading occupancy sources: {:#?}", e);
+    }
 
-    let sensor_data = match parse_sensor_data(sensor_reader_1) {
-        Ok(r) => r,
-        Err(e) => panic!("Something went worng reading sensor csv 1: {:#?}", e),
-    };
+    let sensor_data = DashMap::new();
+    for file in config.sensor_files.iter() {
+        let reader = match read_csv(file) {
+            Ok(r) => r,
+            Err(e) => panic!("Something went worng reading csv {}: {:#?}", file, e),
+        };
+        let data = match parse_sensor_data(reader) {
+            Ok(r) => r,
+            Err(e) => panic!("Something went worng reading sensor csv {}: {:#?}", file, e),
+        };
+        for val_ref in data.into_iter() {
+            sensor_data.insert(val_ref.0, val_ref.1);
+        }
+    }
 
-    let sensor_data_2 = match parse_sensor_data(sensor_reader_2) {
-        Ok(r) => r,
-        Err(e) => panic!("Something went worng reading sensor csv 2: {:#?}", e),
-    };
+    (sensor_data, location_data, weather_data)
+}
 
-    for val_ref in sensor_data_2.into_iter() {
-        sensor_data.insert(val_ref.0, val_ref.1);
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+    use crate::testing::series;
+
+    /// Three days of half an hour in two rooms, CO2 counting the minutes from 08:00.
+    fn write_dataset(dir: &Path) {
+        let config = Config::default();
+        let mut data = HashMap::new();
+        for location in [SensorLocation::U11, SensorLocation::U4c] {
+            let mut days = HashMap::new();
+            for start in ["2023-01-09 08:00", "2023-01-10 08:00", "2023-01-11 08:00"] {
+                let co2: Vec<f32> = (0..30).map(|minute| minute as f32).collect();
+                let mut records = series(start, "co2", &co2);
+                records.iter_mut().for_each(|record| record.1.location = location.clone());
+                days.insert(records[0].0.date(), records);
+            }
+            data.insert(location, generate_windows(&days, 10, 0));
+        }
+        let days = collect_location_days(data, &Calendar::default());
+        let folds = shuffle_and_split_into_folds(days, 2, config.seed);
+
+        fs::create_dir_all(dir).unwrap();
+        export_window_table(&folds, dir).unwrap();
+        let folds: Vec<Vec<DayRows>> = folds.into_iter().map(|fold| restructure_data_to_output(fold, &config)).collect();
+        export_data(&folds, dir, &config).unwrap();
     }
 
-    for val_ref in weather_data_2.into_iter() {
-        weather_data.insert(val_ref.0, val_ref.1);
+    #[test]
+    fn window_table_matches_folds() {
+        let dir = std::env::temp_dir().join(format!("windows-{}", process::id()));
+        write_dataset(&dir);
+
+        let mut windows: BTreeMap<i32, (usize, NaiveDateTime, NaiveDateTime)> = BTreeMap::new();
+        let mut reader = csv::Reader::from_path(dir.join("windows.csv")).unwrap();
+        let headers = reader.headers().unwrap().clone();
+        for row in reader.records() {
+            let row = row.unwrap();
+            let field = |name: &str| row.get(headers.iter().position(|h| h == name).unwrap()).unwrap().to_string();
+            let time = |name: &str| NaiveDateTime::parse_from_str(&field(name), "%Y-%m-%dT%H:%M:%S").unwrap();
+            windows.insert(field("window_id").parse().unwrap(), (field("fold").parse().unwrap(), time("start"), time("end")));
+        }
+        // 3 days in 2 rooms with 20 windows each
+        assert_eq!(windows.keys().copied().collect::<Vec<_>>(), (1..=120).collect::<Vec<_>>());
+
+        let co2 = TargetRow::header(&Config::default()).iter().position(|name| name == "co2").unwrap();
+        for fold in 0..2 {
+            let mut rows: BTreeMap<i32, Vec<f32>> = BTreeMap::new();
+            let mut reader = csv::Reader::from_path(dir.join(fold_file_name(fold))).unwrap();
+            for row in reader.records() {
+                let row = row.unwrap();
+                rows.entry(row[0].parse().unwrap()).or_default().push(row[co2].parse().unwrap());
+            }
+            let listed: Vec<i32> = windows.iter().filter(|(_, (f, ..))| *f == fold + 1).map(|(id, _)| *id).collect();
+            assert_eq!(rows.keys().copied().collect::<Vec<_>>(), listed, "fold {}", fold + 1);
+            for (id, values) in rows {
+                let (_, start, end) = windows[&id];
+                assert_eq!(values.len(), 10);
+                assert_eq!(values[0], start.minute() as f32, "window {}", id);
+                assert_eq!(values[9], end.minute() as f32, "window {}", id);
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
     }
 
-    (sensor_data, location_data, weather_data)
-}
\ No newline at end of file
+    #[test]
+    fn window_ids_are_stable() {
+        let dirs = [1, 2].map(|run| std::env::temp_dir().join(format!("windows-{}-{}", process::id(), run)));
+        dirs.iter().for_each(|dir| write_dataset(dir));
+        for file in ["windows.csv", "fold_1.csv", "fold_2.csv"] {
+            let [first, second] = dirs.each_ref().map(|dir| fs::read(dir.join(file)).unwrap());
+            assert!(first == second, "{} differs between runs", file);
+        }
+        dirs.iter().for_each(|dir| fs::remove_dir_all(dir).unwrap());
+    }
+}
@@ -0,0 +1,301 @@
+use std::{collections::BTreeMap, error::Error, fs::File, io::Read, path::Path};
+
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::{Config, ScalingMode},
+    fold_file_name,
+    scaling::FittedScalers,
+    LocationDay,
+    SensorLocation,
+    TargetRow,
+};
+
+/// Everything needed to trace an exported dataset back to the inputs and
+/// settings that produced it. Written as `manifest.json` next to the folds.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub tool: String,
+    pub version: String,
+    pub created_at: String,
+    /// Hash of the tool version, the config and the input contents, see `provenance`.
+    pub provenance: String,
+    pub inputs: Vec<InputFile>,
+    pub config: Config,
+    /// Scalers fitted on the whole dataset, only with global scaling.
+    pub scalers: Option<FittedScalers>,
+    /// Scalers fitted on the training side of every split, only with per split scaling.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub split_scalers: Vec<FittedScalers>,
+    pub columns: Vec<Column>,
+    pub folds: Vec<FoldSummary>,
+    /// Seconds spent in each stage of the run.
+    pub timings: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InputFile {
+    pub path: String,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Column {
+    pub name: String,
+    pub dtype: String,
+    /// `id`, `feature` or `target`. Sensor and weather targets are NaN at
+    /// minutes without a reading of their field earlier that day.
+    pub role: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FoldSummary {
+    pub fold: usize,
+    /// Fold file, only written when the folds are shared between splits.
+    pub file: Option<String>,
+    pub windows: usize,
+    pub rows: usize,
+    /// Rows per target class, only with a classification `occupancy_target`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub classes: BTreeMap<i32, usize>,
+    pub days: Vec<FoldDay>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FoldDay {
+    pub location: SensorLocation,
+    pub date: NaiveDate,
+    pub windows: usize,
+}
+
+impl Manifest {
+    pub fn new(
+        config: &Config,
+        provenance: String,
+        inputs: Vec<InputFile>,
+        scalers: Option<FittedScalers>,
+        split_scalers: Vec<FittedScalers>,
+        folds: Vec<FoldSummary>,
+        timings: BTreeMap<String, f64>,
+    ) -> Self {
+        let targets = TargetRow::target_columns(config);
+        let columns = TargetRow::columns(config)
+            .into_iter()
+            .map(|(name, dtype)| {
+                let role = match name.as_str() {
+                    "window_id" => "id",
+                    name if targets.iter().any(|target| target == name) => "target",
+                    _ => "feature",
+                };
+                Column { name, dtype: dtype.to_string(), role: role.to_string() }
+            })
+            .collect();
+
+        Manifest {
+            tool: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: Utc::now().to_rfc3339(),
+            provenance,
+            inputs,
+            config: config.clone(),
+            scalers,
+            split_scalers,
+            columns,
+            folds,
+            timings,
+        }
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        let file = File::create(dir.join("manifest.json"))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+fn class_counts(fold: &[LocationDay], config: &Config) -> BTreeMap<i32, usize> {
+    let mut classes = BTreeMap::new();
+    if !config.occupancy_target.is_classification() {
+        return classes;
+    }
+    for (_, _, people, ..) in fold.iter().flat_map(|day| day.windows.iter().flatten()) {
+        *classes.entry(config.occupancy_target.class(people.people)).or_default() += 1;
+    }
+    classes
+}
+
+/// Prints the share of every target class over all folds.
+pub fn print_class_balance(folds: &[FoldSummary]) {
+    let mut classes: BTreeMap<i32, usize> = BTreeMap::new();
+    for (class, rows) in folds.iter().flat_map(|fold| fold.classes.iter()) {
+        *classes.entry(*class).or_default() += rows;
+    }
+    let total: usize = classes.values().sum();
+    for (class, rows) in classes {
+        println!("Class {}: {} rows ({:.1}%)", class, rows, 100. * rows as f64 / total as f64);
+    }
+}
+
+pub fn summarize_folds(folded_data: &[Vec<LocationDay>], config: &Config) -> Vec<FoldSummary> {
+    folded_data
+        .iter()
+        .enumerate()
+        .map(|(fold_index, fold)| FoldSummary {
+            fold: fold_index + 1,
+            file: match config.scaling {
+                ScalingMode::Global => Some(fold_file_name(fold_index)),
+                ScalingMode::PerSplit => None,
+            },
+            windows: fold.iter().map(|day| day.windows.len()).sum(),
+            rows: fold.iter().flat_map(|day| day.windows.iter()).map(|window| window.len()).sum(),
+            classes: class_counts(fold, config),
+            days: fold
+                .iter()
+                .map(|day| FoldDay {
+                    location: day.location.clone(),
+                    date: day.date,
+                    windows: day.windows.len(),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Identifies the dataset a run produces: the tool version, every config value
+/// that changes the output and the contents of the inputs. Where the files
+/// live (input paths, occupancy source paths, output directory) is left out
+/// on purpose.
+pub fn provenance(config: &Config, inputs: &[InputFile]) -> Result<String, Box<dyn Error>> {
+    let mut config = serde_json::to_value(config)?;
+    if let Some(config) = config.as_object_mut() {
+        for key in [
+            "sensor_files",
+            "weather_files",
+            "school_file",
+            "scalers_file",
+            "calendar_file",
+            "rooms_file",
+            "output_dir",
+            "dataset_name",
+        ] {
+            config.remove(key);
+        }
+        // the contents of the occupancy files are part of `inputs`
+        if let Some(sources) = config.get_mut("occupancy_sources").and_then(|s| s.as_object_mut()) {
+            for source in sources.values_mut().filter_map(|source| source.as_object_mut()) {
+                source.remove("file");
+            }
+        }
+    }
+    let inputs: Vec<&str> = inputs.iter().map(|i| i.sha256.as_str()).collect();
+
+    let description = serde_json::json!({
+        "tool": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "config": config,
+        "inputs": inputs,
+    });
+    Ok(to_hex(&Sha256::digest(serde_json::to_vec(&description)?)))
+}
+
+/// Provenance recorded in the manifest of an existing dataset directory.
+pub fn read_provenance(dir: &Path) -> Option<String> {
+    let file = File::open(dir.join("manifest.json")).ok()?;
+    let manifest: serde_json::Value = serde_json::from_reader(file).ok()?;
+    manifest.get("provenance")?.as_str().map(String::from)
+}
+
+/// Hashes every input file named in the config, in the order they are parsed.
+pub fn hash_inputs(config: &Config) -> Result<Vec<InputFile>, Box<dyn Error>> {
+    config
+        .sensor_files
+        .iter()
+        .chain(config.weather_files.iter())
+        .chain(std::iter::once(&config.school_file))
+        .chain(config.scalers_file.iter())
+        .chain(config.calendar_file.iter())
+        .chain(config.rooms_file.iter())
+        .map(|path| path.as_str())
+        .chain(config.occupancy_sources.values().map(|source| source.file()))
+        .map(hash_file)
+        .collect()
+}
+
+fn hash_file(path: &str) -> Result<InputFile, Box<dyn Error>> {
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => return Err(format!("Error opening {}: {}", path, e).into()),
+    };
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 16];
+    let mut bytes = 0;
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        bytes += read as u64;
+    }
+
+    Ok(InputFile {
+        path: path.to_string(),
+        bytes,
+        sha256: to_hex(&hasher.finalize()),
+    })
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::occupancy::{MinuteCounts, OccupancySourceKind};
+
+    fn config(dir: &str) -> Config {
+        let minute_counts = MinuteCounts { file: format!("{}/u11_counts.csv", dir) };
+        Config {
+            sensor_files: vec![format!("{}/sensors.csv", dir)],
+            weather_files: vec![format!("{}/weather.csv", dir)],
+            school_file: format!("{}/school.csv", dir),
+            calendar_file: Some(format!("{}/calendar.ics", dir)),
+            occupancy_sources: BTreeMap::from([(SensorLocation::U11, OccupancySourceKind::MinuteCounts(minute_counts))]),
+            output_dir: format!("{}/out", dir),
+            dataset_name: Some(dir.to_string()),
+            ..Config::default()
+        }
+    }
+
+    /// The same contents wherever the files are.
+    fn inputs(config: &Config) -> Vec<InputFile> {
+        let mut paths = config.sensor_files.clone();
+        paths.extend(config.weather_files.iter().cloned());
+        paths.push(config.school_file.clone());
+        paths.extend(config.calendar_file.iter().cloned());
+        paths.extend(config.occupancy_sources.values().map(|source| source.file().to_string()));
+        paths
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| InputFile { path, bytes: 10, sha256: format!("{:064x}", i) })
+            .collect()
+    }
+
+    #[test]
+    fn provenance_ignores_paths() {
+        let (here, there) = (config("data"), config("/mnt/archive/2024"));
+        let expected = provenance(&here, &inputs(&here)).unwrap();
+        assert_eq!(provenance(&there, &inputs(&there)).unwrap(), expected);
+
+        let other = Config { window_size: 60, ..config("data") };
+        assert_ne!(provenance(&other, &inputs(&other)).unwrap(), expected);
+        let mut other_contents = inputs(&here);
+        other_contents.last_mut().unwrap().sha256 = format!("{:064x}", 99);
+        assert_ne!(provenance(&here, &other_contents).unwrap(), expected);
+    }
+}
@@ -0,0 +1,277 @@
+use std::{collections::BTreeMap, error::Error, fs::File, path::Path};
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, Timelike};
+use dashmap::DashMap;
+use serde::Serialize;
+
+use crate::{
+    config::{Config, MassBalance},
+    rooms::Rooms,
+    scalers::quantile,
+    timetable::{slot_position, slot_start},
+    SensedPeople,
+    Sensor,
+    SensorLocation,
+};
+
+/// Columns of the estimates, written after the psychrometric ones.
+pub static MASS_BALANCE_COLUMNS: [&str; 2] = ["co2_occupancy", "air_exchange_rate"];
+
+/// Decay is only trusted this far (ppm) above the outdoor level, closer to it
+/// the sensor noise dominates.
+static MIN_EXCESS: f32 = 50.;
+/// Decaying minutes needed before a day gets its own air exchange rate.
+static MIN_DECAY_MINUTES: usize = 10;
+
+/// Estimates of one room.
+#[derive(Debug, Default)]
+pub struct RoomEstimate {
+    /// Air changes per hour, by the school day after the night they were fitted on.
+    air_exchange: BTreeMap<NaiveDate, f32>,
+    /// Estimated people per minute with a CO2 reading.
+    people: BTreeMap<NaiveDateTime, f32>,
+}
+
+impl RoomEstimate {
+    /// Air exchange rate used at a minute, see `rate_day`.
+    fn air_exchange(&self, ndt: &NaiveDateTime, mass_balance: &MassBalance) -> f32 {
+        self.air_exchange.get(&rate_day(ndt)).copied().unwrap_or(mass_balance.default_air_exchange)
+    }
+}
+
+/// Day whose air exchange rate a minute outside of the timetable tells
+/// about: the decay after school and through the night until the first slot
+/// is used for the next school day.
+fn decay_day(ndt: &NaiveDateTime) -> NaiveDate {
+    match slot_start(0) {
+        Some((first, _)) if ndt.time() < first => ndt.date(),
+        _ => ndt.date() + Duration::days(1),
+    }
+}
+
+/// Day whose air exchange rate a minute uses. School hours and the afternoon
+/// use the rate of the night before, the minutes before the first slot are
+/// part of that night themselves and use the one of the night before it, so
+/// no minute's estimate depends on anything later.
+fn rate_day(ndt: &NaiveDateTime) -> NaiveDate {
+    decay_day(ndt) - Duration::days(1)
+}
+
+/// Index of the first reading in the last `minutes` before each reading.
+fn span_starts(series: &[(NaiveDateTime, f32)], minutes: usize) -> Vec<usize> {
+    let mut first = 0;
+    series
+        .iter()
+        .map(|(ndt, _)| {
+            while *ndt - series[first].0 >= Duration::minutes(minutes as i64) {
+                first += 1;
+            }
+            first
+        })
+        .collect()
+}
+
+fn hours(from: &NaiveDateTime, to: &NaiveDateTime) -> f32 {
+    (*to - *from).num_seconds() as f32 / 3600.
+}
+
+/// CO2 slope in ppm/h over the last `slope_minutes`, from the first reading
+/// in that span to the current one.
+fn slopes(series: &[(NaiveDateTime, f32)], starts: &[usize]) -> Vec<f32> {
+    series
+        .iter()
+        .zip(starts)
+        .map(|((ndt, co2), first)| {
+            let (start, start_co2) = series[*first];
+            let hours = hours(&start, ndt);
+            if hours > 0. { (co2 - start_co2) / hours } else { 0. }
+        })
+        .collect()
+}
+
+fn estimate_room(series: &[(NaiveDateTime, f32)], mass_balance: &MassBalance, volume: f32) -> RoomEstimate {
+    let starts = span_starts(series, mass_balance.slope_minutes);
+    let slopes = slopes(series, &starts);
+
+    // with nobody in the room the excess over outdoor CO2 decays at the air
+    // exchange rate, dC/dt = -λ·(C - C_out), so over the slope span
+    // λ = ln(excess at the start / excess now) / hours
+    let mut decay: BTreeMap<NaiveDate, Vec<f32>> = BTreeMap::new();
+    for ((ndt, co2), first) in series.iter().zip(starts) {
+        let (start, start_co2) = series[first];
+        let excess = co2 - mass_balance.outdoor_co2;
+        let start_excess = start_co2 - mass_balance.outdoor_co2;
+        if slot_position(ndt).is_none() && start_excess > excess && excess > MIN_EXCESS {
+            decay.entry(decay_day(ndt)).or_default().push((start_excess / excess).ln() / hours(&start, ndt));
+        }
+    }
+    let air_exchange = decay
+        .into_iter()
+        .filter(|(_, rates)| rates.len() >= MIN_DECAY_MINUTES)
+        .map(|(date, mut rates)| {
+            rates.sort_by(|a, b| a.total_cmp(b));
+            (date, quantile(&rates, 0.5) as f32)
+        })
+        .collect();
+    let mut estimate = RoomEstimate { air_exchange, people: BTreeMap::new() };
+
+    // n = V·(dC/dt + λ·(C - C_out)) / G
+    for ((ndt, co2), slope) in series.iter().zip(slopes) {
+        let rate = estimate.air_exchange(ndt, mass_balance);
+        let generated = volume * (slope + rate * (co2 - mass_balance.outdoor_co2)) * 1e-6;
+        estimate.people.insert(*ndt, (generated / mass_balance.generation_rate).max(0.));
+    }
+    estimate
+}
+
+/// Estimates every room from its raw CO2 readings, before baselines and scaling.
+pub fn estimate(
+    sensor_data: &DashMap<NaiveDateTime, Vec<Sensor>>,
+    mass_balance: &MassBalance,
+    rooms: &Rooms,
+) -> BTreeMap<SensorLocation, RoomEstimate> {
+    let mut series: BTreeMap<SensorLocation, Vec<(NaiveDateTime, f32)>> = BTreeMap::new();
+    for item in sensor_data.iter() {
+        for sensor in item.value() {
+            if let Some(co2) = sensor.co2 {
+                series.entry(sensor.location.clone()).or_default().push((*item.key(), co2));
+            }
+        }
+    }
+    series
+        .into_iter()
+        .map(|(location, mut series)| {
+            series.sort_by_key(|(ndt, _)| *ndt);
+            let volume = mass_balance
+                .volumes
+                .get(&location)
+                .copied()
+                .or_else(|| rooms.get(&location).map(|room| room.volume).filter(|v| *v > 0.))
+                .unwrap_or(mass_balance.default_volume);
+            let estimate = estimate_room(&series, mass_balance, volume);
+            (location, estimate)
+        })
+        .collect()
+}
+
+/// Appends the estimated people and air exchange rate to the derived values
+/// of every sensor, 0 people for minutes without a CO2 reading.
+pub fn add_mass_balance(
+    sensor_data: &DashMap<NaiveDateTime, Vec<Sensor>>,
+    estimates: &BTreeMap<SensorLocation, RoomEstimate>,
+    mass_balance: &MassBalance,
+) {
+    if !mass_balance.features {
+        return;
+    }
+    for mut item in sensor_data.iter_mut() {
+        let ndt = *item.key();
+        for sensor in item.value_mut().iter_mut() {
+            let (people, rate) = match estimates.get(&sensor.location) {
+                Some(estimate) => (
+                    estimate.people.get(&ndt).copied().unwrap_or_default(),
+                    estimate.air_exchange(&ndt, mass_balance),
+                ),
+                None => (0., mass_balance.default_air_exchange),
+            };
+            sensor.derived.extend([people, rate]);
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BaselineRow<'a> {
+    location: &'a SensorLocation,
+    time: NaiveDateTime,
+    people: i32,
+    estimated_people: f32,
+    air_exchange_rate: f32,
+}
+
+/// Writes the estimate of every kept room and minute of the day next to the
+/// counted people to `mass_balance_baseline.csv`, for comparing models against.
+pub fn write_baseline(
+    estimates: &BTreeMap<SensorLocation, RoomEstimate>,
+    people_data: &DashMap<NaiveDateTime, Vec<SensedPeople>>,
+    out_dir: &Path,
+    config: &Config,
+    mass_balance: &MassBalance,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(File::create(out_dir.join("mass_balance_baseline.csv"))?);
+    let (mut count, mut absolute_error) = (0, 0.);
+
+    for (location, estimate) in estimates.iter() {
+        if config.excluded_locations.contains(location) {
+            continue;
+        }
+        for (ndt, estimated_people) in estimate.people.iter() {
+            if ndt.hour() < config.start_hour || ndt.hour() >= config.end_hour {
+                continue;
+            }
+            let people = people_data
+                .get(ndt)
+                .and_then(|p| p.value().iter().find(|p| p.sensor_location == *location).map(|p| p.people))
+                .unwrap_or_default();
+            count += 1;
+            absolute_error += (estimated_people - people as f32).abs() as f64;
+            writer.serialize(BaselineRow {
+                location,
+                time: *ndt,
+                people,
+                estimated_people: *estimated_people,
+                air_exchange_rate: estimate.air_exchange(ndt, mass_balance),
+            })?;
+        }
+    }
+    writer.flush()?;
+    if count > 0 {
+        println!("Mass balance baseline MAE: {:.3} people over {} minutes", absolute_error / count as f64, count);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::time;
+
+    /// Excess over outdoor CO2 decaying at `rate` per hour, one reading per minute.
+    fn decay(start: &str, minutes: i64, excess: f32, rate: f32, mass_balance: &MassBalance) -> Vec<(NaiveDateTime, f32)> {
+        (0..minutes)
+            .map(|minute| {
+                let co2 = mass_balance.outdoor_co2 + excess * (-rate * minute as f32 / 60.).exp();
+                (time(start) + Duration::minutes(minute), co2)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recovers_air_exchange_and_people() {
+        let mass_balance = MassBalance::default();
+        let volume = 180.;
+        // 20 people at 2 air changes per hour keep the excess at G·n / (V·λ)
+        let steady = mass_balance.generation_rate * 20. * 1e6 / (volume * 2.);
+        let mut series = decay("2023-01-09 16:00", 120, 1000., 2., &mass_balance);
+        series.extend(decay("2023-01-10 06:00", 60, 800., 2., &mass_balance));
+        series.extend((0..50).map(|minute| (time("2023-01-10 07:40") + Duration::minutes(minute), mass_balance.outdoor_co2 + steady)));
+
+        let estimate = estimate_room(&series, &mass_balance, volume);
+        let rate = estimate.air_exchange(&time("2023-01-10 08:20"), &mass_balance);
+        assert!((rate - 2.).abs() < 1e-3, "{}", rate);
+        let people = estimate.people[&time("2023-01-10 08:20")];
+        assert!((people - 20.).abs() < 0.05, "{}", people);
+    }
+
+    #[test]
+    fn mornings_use_the_rate_of_the_night_before() {
+        let mass_balance = MassBalance::default();
+        let mut series = decay("2023-01-09 16:00", 120, 1000., 2., &mass_balance);
+        series.extend(decay("2023-01-10 06:00", 60, 800., 2., &mass_balance));
+
+        let estimate = estimate_room(&series, &mass_balance, 180.);
+        // 06:30 is part of the decay fitted for the 10th, it keeps the 9th's default
+        assert_eq!(estimate.air_exchange(&time("2023-01-10 06:30"), &mass_balance), mass_balance.default_air_exchange);
+        assert_eq!(estimate.air_exchange(&time("2023-01-09 17:00"), &mass_balance), mass_balance.default_air_exchange);
+        assert!((estimate.air_exchange(&time("2023-01-10 07:30"), &mass_balance) - 2.).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,463 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    fs::File,
+    path::Path,
+};
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, Timelike};
+use csv::StringRecord;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, parse_location_data, SensedPeople, SensorLocation};
+
+/// Where the people counts of a room come from.
+pub trait OccupancySource {
+    /// People in `location` per minute. A minute can appear more than once.
+    fn read(&self, location: &SensorLocation) -> Result<Vec<(NaiveDateTime, i32)>, Box<dyn Error>>;
+}
+
+/// A school data export, one count per timetable slot, like `school_file`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlotCounts {
+    pub file: String,
+}
+
+/// csv with a `time` and a `people` column, one row per minute. Rows that
+/// can not be read are reported with their line and skipped.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MinuteCounts {
+    pub file: String,
+}
+
+/// csv of door counter events with a `time` and a `direction` (`in` / `out`)
+/// column and an optional `count` column for groups, turned into a running
+/// count per minute. The count starts at 0 every midnight and never goes
+/// below 0, so a missed entry does not carry over. A row that can not be read
+/// stops the run, without it every later count of the day would be off.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntryExitLog {
+    pub file: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OccupancySourceKind {
+    Slots(SlotCounts),
+    MinuteCounts(MinuteCounts),
+    EntryExit(EntryExitLog),
+}
+
+impl OccupancySourceKind {
+    pub fn file(&self) -> &str {
+        match self {
+            OccupancySourceKind::Slots(s) => &s.file,
+            OccupancySourceKind::MinuteCounts(s) => &s.file,
+            OccupancySourceKind::EntryExit(s) => &s.file,
+        }
+    }
+}
+
+impl OccupancySource for OccupancySourceKind {
+    fn read(&self, location: &SensorLocation) -> Result<Vec<(NaiveDateTime, i32)>, Box<dyn Error>> {
+        match self {
+            OccupancySourceKind::Slots(s) => s.read(location),
+            OccupancySourceKind::MinuteCounts(s) => s.read(location),
+            OccupancySourceKind::EntryExit(s) => s.read(location),
+        }
+    }
+}
+
+/// Timestamps as `2023-01-09T08:00:00`, `2023-01-09 08:00:00` or
+/// `2023-01-09 08:00`, seconds are dropped.
+fn parse_time(value: &str) -> Result<NaiveDateTime, Box<dyn Error>> {
+    let value = value.trim().trim_end_matches('Z');
+    let parsed = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok());
+    match parsed.and_then(|ndt| ndt.with_second(0)) {
+        Some(ndt) => Ok(ndt),
+        None => Err(format!("Error parsing occupancy time {}", value).into()),
+    }
+}
+
+/// Rows of a csv by header name.
+fn read_rows(file: &str) -> Result<(StringRecord, Vec<StringRecord>), Box<dyn Error>> {
+    let mut reader = csv::Reader::from_reader(File::open(file)?);
+    let headers = reader.headers()?.clone();
+    let rows = reader.records().collect::<Result<Vec<_>, _>>()?;
+    Ok((headers, rows))
+}
+
+/// Line of a row in its file, for error messages.
+fn line(row: &StringRecord) -> u64 {
+    row.position().map_or(0, |position| position.line())
+}
+
+fn column(headers: &StringRecord, name: &str, file: &str) -> Result<usize, Box<dyn Error>> {
+    match headers.iter().position(|h| h == name) {
+        Some(i) => Ok(i),
+        None => Err(format!("{} has no {} column", file, name).into()),
+    }
+}
+
+impl OccupancySource for SlotCounts {
+    fn read(&self, location: &SensorLocation) -> Result<Vec<(NaiveDateTime, i32)>, Box<dyn Error>> {
+        let data = parse_location_data(csv::Reader::from_path(&self.file)?)?;
+        Ok(data
+            .iter()
+            .flat_map(|item| {
+                let ndt = *item.key();
+                item.value()
+                    .iter()
+                    .filter(|p| p.sensor_location == *location)
+                    .map(|p| (ndt, p.people))
+                    .collect::<Vec<_>>()
+            })
+            .collect())
+    }
+}
+
+impl OccupancySource for MinuteCounts {
+    fn read(&self, _: &SensorLocation) -> Result<Vec<(NaiveDateTime, i32)>, Box<dyn Error>> {
+        let (headers, rows) = read_rows(&self.file)?;
+        let (time, people) = (column(&headers, "time", &self.file)?, column(&headers, "people", &self.file)?);
+        let parse = |row: &StringRecord| -> Result<(NaiveDateTime, i32), Box<dyn Error>> {
+            let ndt = parse_time(row.get(time).unwrap_or_default())?;
+            Ok((ndt, row.get(people).unwrap_or_default().trim().parse::<i32>()?))
+        };
+        Ok(rows
+            .iter()
+            .filter_map(|row| match parse(row) {
+                Ok(count) => Some(count),
+                Err(e) => {
+                    println!("Skipping line {} of {}: {}", line(row), self.file, e);
+                    None
+                },
+            })
+            .collect())
+    }
+}
+
+impl OccupancySource for EntryExitLog {
+    fn read(&self, _: &SensorLocation) -> Result<Vec<(NaiveDateTime, i32)>, Box<dyn Error>> {
+        let (headers, rows) = read_rows(&self.file)?;
+        let (time, direction) = (column(&headers, "time", &self.file)?, column(&headers, "direction", &self.file)?);
+        let count = headers.iter().position(|h| h == "count");
+
+        // net change per minute, grouped by day
+        let mut changes: BTreeMap<NaiveDate, BTreeMap<NaiveDateTime, i32>> = BTreeMap::new();
+        let parse = |row: &StringRecord| -> Result<(NaiveDateTime, i32), Box<dyn Error>> {
+            let ndt = parse_time(row.get(time).unwrap_or_default())?;
+            let count = match count.and_then(|i| row.get(i)).filter(|c| !c.trim().is_empty()) {
+                Some(c) => c.trim().parse::<i32>()?,
+                None => 1,
+            };
+            let sign = match row.get(direction).unwrap_or_default().trim().to_lowercase().as_str() {
+                "in" | "entry" | "enter" => 1,
+                "out" | "exit" | "leave" => -1,
+                other => return Err(format!("unknown direction {}", other).into()),
+            };
+            Ok((ndt, sign * count))
+        };
+        for row in rows.iter() {
+            let (ndt, change) = match parse(row) {
+                Ok(c) => c,
+                Err(e) => return Err(format!("Error in line {} of {}: {}", line(row), self.file, e).into()),
+            };
+            *changes.entry(ndt.date()).or_default().entry(ndt).or_default() += change;
+        }
+
+        let mut counts = Vec::new();
+        for (date, day_changes) in changes {
+            let mut people = 0;
+            let midnight = date.and_hms_opt(0, 0, 0).unwrap_or_default();
+            for minute in 0..24 * 60 {
+                let ndt = midnight + Duration::minutes(minute);
+                people = (people + day_changes.get(&ndt).copied().unwrap_or_default()).max(0);
+                counts.push((ndt, people));
+            }
+        }
+        Ok(counts)
+    }
+}
+
+/// Replaces the `school_file` counts of every room in `occupancy_sources`
+/// with the counts of its source, on the days the source covers. Other days
+/// keep the school data. `scheduled` still comes from the timetable.
+pub fn apply_sources(
+    people_data: &DashMap<NaiveDateTime, Vec<SensedPeople>>,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    for (location, source) in config.occupancy_sources.iter() {
+        let counts = source.read(location)?;
+        let from_timetable = matches!(source, OccupancySourceKind::Slots(_));
+        let days: HashSet<NaiveDate> = counts.iter().map(|(ndt, _)| ndt.date()).collect();
+
+        let mut scheduled = HashSet::new();
+        for mut item in people_data.iter_mut() {
+            let ndt = *item.key();
+            if !days.contains(&ndt.date()) {
+                continue;
+            }
+            item.value_mut().retain(|p| {
+                let replaced = p.sensor_location == *location;
+                if replaced && p.scheduled {
+                    scheduled.insert(ndt);
+                }
+                !replaced
+            });
+        }
+
+        println!("Occupancy of {:?} from {} on {} days", location, source.file(), days.len());
+        for (ndt, people) in counts {
+            people_data.entry(ndt).or_default().push(SensedPeople {
+                sensor_location: location.clone(),
+                people,
+                scheduled: from_timetable || scheduled.contains(&ndt),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// How several counts of one room and minute, e.g. two timetable rows for the
+/// same slot, are turned into one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictRule {
+    Max,
+    /// Rounded to the nearest person.
+    Mean,
+    /// The last row of the file, occupancy sources come after `school_file`.
+    #[default]
+    Latest,
+    /// Stop with an error.
+    Reject,
+}
+
+/// A room and minute with differing counts.
+#[derive(Debug, Clone, Serialize)]
+pub struct OccupancyConflict {
+    pub location: SensorLocation,
+    pub time: NaiveDateTime,
+    /// The counts in file order, `;` separated.
+    pub counts: String,
+    pub resolved: i32,
+}
+
+/// Leaves one count per room and minute. Repeated equal counts are merged
+/// silently, differing ones are resolved by `rule` and returned. A minute is
+/// scheduled if any of its counts is.
+pub fn resolve_conflicts(
+    people_data: &DashMap<NaiveDateTime, Vec<SensedPeople>>,
+    rule: ConflictRule,
+) -> Result<Vec<OccupancyConflict>, Box<dyn Error>> {
+    let mut conflicts = Vec::new();
+    for mut item in people_data.iter_mut() {
+        let time = *item.key();
+        let mut grouped: BTreeMap<SensorLocation, Vec<SensedPeople>> = BTreeMap::new();
+        for people in item.value_mut().drain(..) {
+            grouped.entry(people.sensor_location.clone()).or_default().push(people);
+        }
+        for (location, duplicates) in grouped {
+            let counts: Vec<i32> = duplicates.iter().map(|p| p.people).collect();
+            let scheduled = duplicates.iter().any(|p| p.scheduled);
+            let resolved = match rule {
+                _ if counts.iter().all(|c| *c == counts[0]) => counts[0],
+                ConflictRule::Max => counts.iter().copied().max().unwrap_or_default(),
+                ConflictRule::Mean => (counts.iter().sum::<i32>() as f32 / counts.len() as f32).round() as i32,
+                ConflictRule::Latest | ConflictRule::Reject => counts[counts.len() - 1],
+            };
+            if counts.iter().any(|c| *c != resolved) {
+                conflicts.push(OccupancyConflict {
+                    location: location.clone(),
+                    time,
+                    counts: counts.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(";"),
+                    resolved,
+                });
+            }
+            item.value_mut().push(SensedPeople { sensor_location: location, people: resolved, scheduled });
+        }
+    }
+    conflicts.sort_by(|a, b| (&a.location, a.time).cmp(&(&b.location, b.time)));
+
+    if rule == ConflictRule::Reject {
+        if let Some(first) = conflicts.first() {
+            return Err(format!(
+                "{} conflicting occupancy counts, the first for {:?} at {}: {}",
+                conflicts.len(),
+                first.location,
+                first.time,
+                first.counts
+            )
+            .into());
+        }
+    }
+    Ok(conflicts)
+}
+
+/// Writes the resolved conflicts to `occupancy_conflicts.csv`, if there are any.
+pub fn write_conflicts(conflicts: &[OccupancyConflict], out_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let rooms: HashSet<&SensorLocation> = conflicts.iter().map(|c| &c.location).collect();
+    println!("Occupancy conflicts: {} minutes in {} rooms", conflicts.len(), rooms.len());
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+    let mut writer = csv::Writer::from_writer(File::create(out_dir.join("occupancy_conflicts.csv"))?);
+    for conflict in conflicts.iter() {
+        writer.serialize(conflict)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use super::*;
+    use crate::testing::time;
+
+    fn temp_csv(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("occupancy-{}-{}.csv", name, process::id()));
+        fs::write(&path, content).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn people(location: SensorLocation, people: i32) -> SensedPeople {
+        SensedPeople { sensor_location: location, people, scheduled: true }
+    }
+
+    /// U11 counted 20, 26 and 20 at 08:00 and twice 15 at 08:01, U4c once.
+    fn duplicated() -> DashMap<NaiveDateTime, Vec<SensedPeople>> {
+        let data = DashMap::new();
+        data.insert(
+            time("2023-01-09 08:00"),
+            vec![people(SensorLocation::U11, 20), people(SensorLocation::U4c, 3), people(SensorLocation::U11, 26), people(SensorLocation::U11, 20)],
+        );
+        data.insert(time("2023-01-09 08:01"), vec![people(SensorLocation::U11, 15), people(SensorLocation::U11, 15)]);
+        data
+    }
+
+    fn resolved(rule: ConflictRule) -> (i32, Vec<OccupancyConflict>) {
+        let data = duplicated();
+        let conflicts = resolve_conflicts(&data, rule).unwrap();
+        for item in data.iter() {
+            assert_eq!(item.value().iter().filter(|p| p.sensor_location == SensorLocation::U11).count(), 1);
+        }
+        let minute = data.get(&time("2023-01-09 08:00")).unwrap();
+        let u11 = minute.iter().find(|p| p.sensor_location == SensorLocation::U11).unwrap().people;
+        (u11, conflicts)
+    }
+
+    #[test]
+    fn rules_resolve_duplicates() {
+        assert_eq!(resolved(ConflictRule::Max).0, 26);
+        assert_eq!(resolved(ConflictRule::Mean).0, 22);
+        assert_eq!(resolved(ConflictRule::Latest).0, 20);
+    }
+
+    #[test]
+    fn conflicts_are_reported_once_and_equal_duplicates_not_at_all() {
+        let (_, conflicts) = resolved(ConflictRule::Max);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].location, SensorLocation::U11);
+        assert_eq!(conflicts[0].time, time("2023-01-09 08:00"));
+        assert_eq!(conflicts[0].counts, "20;26;20");
+        assert_eq!(conflicts[0].resolved, 26);
+    }
+
+    #[test]
+    fn reject_fails_on_conflicts_only() {
+        assert!(resolve_conflicts(&duplicated(), ConflictRule::Reject).is_err());
+        let data = duplicated();
+        data.remove(&time("2023-01-09 08:00"));
+        assert!(resolve_conflicts(&data, ConflictRule::Reject).unwrap().is_empty());
+    }
+
+    #[test]
+    fn latest_is_the_last_row_of_the_file() {
+        let mut rows = String::from("a,date,b,slot,c,room,d,e,f,g,people\n");
+        // enough rows for the parallel parse to split them
+        for people in 1..=200 {
+            rows.push_str(&format!("x,2023-01-09,x,1,x,U11,x,x,x,x,{}\n", people));
+        }
+        let file = temp_csv("school", &rows);
+        let data = parse_location_data(csv::Reader::from_path(&file).unwrap()).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        resolve_conflicts(&data, ConflictRule::Latest).unwrap();
+        for item in data.iter() {
+            assert_eq!(item.value()[0].people, 200);
+        }
+    }
+
+    #[test]
+    fn sources_come_after_the_school_data() {
+        let file = temp_csv("latest", "time,people\n2023-01-09 08:00,7\n2023-01-09 08:00,9\n");
+        let source = OccupancySourceKind::MinuteCounts(MinuteCounts { file: file.clone() });
+        let config = Config { occupancy_sources: BTreeMap::from([(SensorLocation::U11, source)]), ..Config::default() };
+        let data = duplicated();
+        apply_sources(&data, &config).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        let conflicts = resolve_conflicts(&data, ConflictRule::Latest).unwrap();
+        assert_eq!(conflicts.iter().map(|c| c.counts.as_str()).collect::<Vec<_>>(), vec!["7;9"]);
+        let minute = data.get(&time("2023-01-09 08:00")).unwrap();
+        assert_eq!(minute.iter().find(|p| p.sensor_location == SensorLocation::U11).unwrap().people, 9);
+        // the other room keeps its school count
+        assert_eq!(minute.iter().find(|p| p.sensor_location == SensorLocation::U4c).unwrap().people, 3);
+    }
+
+    fn count_at(counts: &[(NaiveDateTime, i32)], at: &str) -> i32 {
+        counts.iter().find(|(ndt, _)| *ndt == time(at)).unwrap().1
+    }
+
+    #[test]
+    fn entry_exit_log_is_a_running_count() {
+        let file = temp_csv(
+            "doors",
+            "time,direction,count\n\
+             2023-01-09 07:55,in,10\n\
+             2023-01-09 08:05,in,\n\
+             2023-01-09 08:05:40,out,3\n\
+             2023-01-09 09:00,out,20\n\
+             2023-01-09 09:10,in,2\n\
+             2023-01-09 23:59,in,5\n\
+             2023-01-10 08:00,out,1\n",
+        );
+        let counts = EntryExitLog { file: file.clone() }.read(&SensorLocation::U11).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        assert_eq!(counts.len(), 2 * 24 * 60);
+        assert_eq!(count_at(&counts, "2023-01-09 07:54"), 0);
+        assert_eq!(count_at(&counts, "2023-01-09 07:55"), 10);
+        // one in and a group of three out in the same minute
+        assert_eq!(count_at(&counts, "2023-01-09 08:05"), 8);
+        // more leave than are counted in, clamped at 0
+        assert_eq!(count_at(&counts, "2023-01-09 09:00"), 0);
+        assert_eq!(count_at(&counts, "2023-01-09 09:10"), 2);
+        assert_eq!(count_at(&counts, "2023-01-09 23:59"), 7);
+        // reset at midnight
+        assert_eq!(count_at(&counts, "2023-01-10 00:00"), 0);
+        assert_eq!(count_at(&counts, "2023-01-10 08:00"), 0);
+    }
+
+    #[test]
+    fn entry_exit_log_reports_the_bad_line() {
+        let file = temp_csv("bad-doors", "time,direction\n2023-01-09 07:55,in\n2023-01-09 08:00,sideways\n");
+        let error = EntryExitLog { file: file.clone() }.read(&SensorLocation::U11).unwrap_err();
+        fs::remove_file(&file).unwrap();
+        assert!(error.to_string().contains("line 3"), "{}", error);
+    }
+
+    #[test]
+    fn minute_counts_skip_bad_rows() {
+        let file = temp_csv("minutes", "time,people\n2023-01-09 08:00,5\n2023-01-09 08:01,\n2023-01-09T08:02:30,9\nlater,3\n");
+        let counts = MinuteCounts { file: file.clone() }.read(&SensorLocation::U11).unwrap();
+        fs::remove_file(&file).unwrap();
+        assert_eq!(counts, vec![(time("2023-01-09 08:00"), 5), (time("2023-01-09 08:02"), 9)]);
+    }
+}
@@ -0,0 +1,97 @@
+use std::{error::Error, fs, path::{Path, PathBuf}, process};
+
+use crate::manifest;
+
+/// Output directory of one dataset. Everything is written into a staging
+/// directory next to the target and only renamed into place by `commit`, so
+/// a crashed run never leaves a half written dataset behind.
+pub struct DatasetDir {
+    pub target: PathBuf,
+    pub staging: PathBuf,
+    committed: bool,
+}
+
+impl DatasetDir {
+    /// Returns `None` when the target already holds a dataset with the same
+    /// provenance and there is nothing to do. A target with a different (or
+    /// unknown) provenance is only replaced when `force` is set.
+    pub fn prepare(
+        root: &Path,
+        name: &str,
+        provenance: &str,
+        force: bool,
+    ) -> Result<Option<Self>, Box<dyn Error>> {
+        let target = root.join(name);
+
+        if target.exists() {
+            match manifest::read_provenance(&target) {
+                Some(existing) if existing == provenance && !force => return Ok(None),
+                Some(existing) if existing != provenance && !force => return Err(format!(
+                    "{} holds a different dataset (provenance {}), use --force to overwrite it",
+                    target.display(),
+                    existing,
+                ).into()),
+                None if !force => return Err(format!(
+                    "{} exists but has no readable manifest, use --force to overwrite it",
+                    target.display(),
+                ).into()),
+                _ => (),
+            }
+        }
+
+        let staging = root.join(format!(".tmp-{}-{}", name, process::id()));
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        fs::create_dir_all(&staging)?;
+
+        Ok(Some(DatasetDir { target, staging, committed: false }))
+    }
+
+    /// Moves the staged dataset into place, replacing any previous one. The
+    /// previous one is moved back when that fails, and only reported when it
+    /// can not be removed afterwards.
+    pub fn commit(mut self) -> Result<PathBuf, Box<dyn Error>> {
+        let previous = self.target.with_file_name(format!(
+            ".old-{}-{}",
+            self.target.file_name().unwrap_or_default().to_string_lossy(),
+            process::id(),
+        ));
+
+        if self.target.exists() {
+            fs::rename(&self.target, &previous)?;
+        }
+        if let Err(e) = fs::rename(&self.staging, &self.target) {
+            if previous.exists() {
+                if let Err(restore) = fs::rename(&previous, &self.target) {
+                    return Err(format!(
+                        "{}, and restoring the previous dataset failed: {}, it is left in {}",
+                        e,
+                        restore,
+                        previous.display(),
+                    ).into());
+                }
+            }
+            return Err(e.into());
+        }
+        self.committed = true;
+
+        // the new dataset is in place, a leftover copy of the old one is no failure
+        if previous.exists() {
+            if let Err(e) = fs::remove_dir_all(&previous) {
+                println!("Error removing the previous dataset {}: {}", previous.display(), e);
+            }
+        }
+        Ok(self.target.clone())
+    }
+}
+
+impl Drop for DatasetDir {
+    fn drop(&mut self) {
+        if !self.committed && self.staging.exists() {
+            if let Err(e) = fs::remove_dir_all(&self.staging) {
+                println!("Error removing staging directory {}: {}", self.staging.display(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use chrono::NaiveDateTime;
+use dashmap::DashMap;
+
+use crate::{config::Config, Sensor, WeatherPoint};
+
+/// Magnus formula coefficients over water (Sonntag 1990), good to about
+/// 0.1 °C between -45 and 60 °C.
+static MAGNUS_A: f32 = 17.62;
+static MAGNUS_B: f32 = 243.12;
+
+/// Dew point more than this above the temperature (°C) is impossible.
+static DEW_POINT_TOLERANCE: f32 = 0.5;
+/// Reported dew point further than this (°C) from the one implied by T and RH.
+static DEW_POINT_MISMATCH: f32 = 2.;
+/// Reported absolute humidity further than this (relative) from the implied one.
+static ABS_HUMIDITY_MISMATCH: f32 = 0.15;
+
+/// Columns of `push_values`, written in front of the other derived features.
+pub static PSYCHROMETRIC_COLUMNS: [&str; 3] = ["temperature_delta", "abs_humidity_delta", "humidity_inconsistent"];
+
+/// Dew point (°C) from temperature (°C) and relative humidity (%).
+pub fn dew_point(temperature: f32, rh: f32) -> f32 {
+    let gamma = (rh / 100.).ln() + MAGNUS_A * temperature / (MAGNUS_B + temperature);
+    MAGNUS_B * gamma / (MAGNUS_A - gamma)
+}
+
+/// Absolute humidity (g/m³) from temperature (°C) and relative humidity (%).
+pub fn abs_humidity(temperature: f32, rh: f32) -> f32 {
+    let saturation_pressure = 6.112 * (MAGNUS_A * temperature / (MAGNUS_B + temperature)).exp();
+    216.7 * (rh / 100. * saturation_pressure) / (273.15 + temperature)
+}
+
+/// Whether the humidity readings of a sensor contradict each other.
+fn inconsistent(sensor: &Sensor) -> bool {
+    if sensor.rh.is_some_and(|rh| !(0. ..=100.).contains(&rh)) {
+        return true;
+    }
+    if let (Some(temperature), Some(dew)) = (sensor.temperature, sensor.dew_point) {
+        if dew > temperature + DEW_POINT_TOLERANCE {
+            return true;
+        }
+    }
+    let (temperature, rh) = match (sensor.temperature, sensor.rh) {
+        (Some(t), Some(rh)) if rh > 0. => (t, rh),
+        _ => return false,
+    };
+    let dew_mismatch = sensor.dew_point.is_some_and(|dew| (dew - dew_point(temperature, rh)).abs() > DEW_POINT_MISMATCH);
+    let abs_mismatch = sensor.abs_humidity.is_some_and(|abs| {
+        let implied = abs_humidity(temperature, rh);
+        (abs - implied).abs() > ABS_HUMIDITY_MISMATCH * implied
+    });
+    dew_mismatch || abs_mismatch
+}
+
+/// Indoor minus outdoor temperature and absolute humidity, and whether the
+/// sensor's humidity readings are inconsistent. A difference without its
+/// indoor reading or without weather is NaN rather than 0, which would look
+/// like a real difference, and the later derived values keep their
+/// positions. Minutes without weather are dropped when merging, the NaN of a
+/// missing indoor reading is written out and not fitted by the scalers.
+fn push_values(sensor: &Sensor, weather: Option<&WeatherPoint>, values: &mut Vec<f32>) {
+    let (temperature_delta, abs_humidity_delta) = match weather {
+        Some(weather) => {
+            let indoor_abs_humidity = sensor
+                .abs_humidity
+                .or_else(|| sensor.temperature.zip(sensor.rh).map(|(t, rh)| abs_humidity(t, rh)));
+            (
+                sensor.temperature.map(|t| t - weather.temperature).unwrap_or(f32::NAN),
+                indoor_abs_humidity
+                    .map(|abs| abs - abs_humidity(weather.temperature, weather.rel_humidity))
+                    .unwrap_or(f32::NAN),
+            )
+        },
+        None => (f32::NAN, f32::NAN),
+    };
+
+    values.extend([
+        temperature_delta,
+        abs_humidity_delta,
+        if inconsistent(sensor) { 1. } else { 0. },
+    ]);
+}
+
+/// Fills in missing dew point and absolute humidity from temperature and RH.
+fn reconstruct(sensor: &mut Sensor) {
+    if let (Some(temperature), Some(rh)) = (sensor.temperature, sensor.rh) {
+        if rh > 0. {
+            sensor.dew_point = sensor.dew_point.or(Some(dew_point(temperature, rh)));
+            sensor.abs_humidity = sensor.abs_humidity.or(Some(abs_humidity(temperature, rh)));
+        }
+    }
+}
+
+/// Works on the raw readings, before baselines and scaling. The features
+/// compare against the outside conditions of the same minute.
+pub fn add_psychrometrics(
+    sensor_data: &DashMap<NaiveDateTime, Vec<Sensor>>,
+    weather_data: &DashMap<NaiveDateTime, WeatherPoint>,
+    config: &Config,
+) {
+    if !config.psychrometric_features && !config.reconstruct_humidity {
+        return;
+    }
+    for mut item in sensor_data.iter_mut() {
+        let weather = weather_data.get(item.key()).map(|w| w.value().clone());
+        for sensor in item.value_mut().iter_mut() {
+            if config.psychrometric_features {
+                let mut values = Vec::new();
+                push_values(sensor, weather.as_ref(), &mut values);
+                sensor.derived = values;
+            }
+            if config.reconstruct_humidity {
+                reconstruct(sensor);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::record;
+
+    /// 20 °C and 50 % with the matching dew point and absolute humidity.
+    fn sensor() -> Sensor {
+        let mut sensor = record("2023-01-09 08:00", &[("temperature", 20.), ("rh", 50.)], 0).1;
+        sensor.dew_point = Some(9.3);
+        sensor.abs_humidity = Some(8.6);
+        sensor
+    }
+
+    #[test]
+    fn magnus_matches_known_values() {
+        assert!((dew_point(20., 50.) - 9.26).abs() < 0.05, "{}", dew_point(20., 50.));
+        assert!((dew_point(15., 100.) - 15.).abs() < 1e-4);
+        assert!((dew_point(0., 80.) + 3.0).abs() < 0.1, "{}", dew_point(0., 80.));
+        assert!((abs_humidity(20., 50.) - 8.63).abs() < 0.05, "{}", abs_humidity(20., 50.));
+        assert!((abs_humidity(30., 100.) - 30.3).abs() < 0.2, "{}", abs_humidity(30., 100.));
+    }
+
+    #[test]
+    fn flags_contradicting_humidity() {
+        assert!(!inconsistent(&sensor()));
+        for change in [
+            |s: &mut Sensor| s.rh = Some(120.),
+            |s: &mut Sensor| s.dew_point = Some(21.),
+            |s: &mut Sensor| s.dew_point = Some(14.),
+            |s: &mut Sensor| s.abs_humidity = Some(12.),
+        ] {
+            let mut sensor = sensor();
+            change(&mut sensor);
+            assert!(inconsistent(&sensor), "{:?}", sensor);
+        }
+        // nothing to compare the dew point with
+        let mut sensor = sensor();
+        sensor.rh = None;
+        sensor.dew_point = Some(14.);
+        assert!(!inconsistent(&sensor));
+    }
+
+    #[test]
+    fn missing_readings_are_nan() {
+        let weather = record("2023-01-09 08:00", &[("outside_temperature", 5.)], 0).3;
+        let mut values = Vec::new();
+        push_values(&sensor(), Some(&weather), &mut values);
+        assert_eq!(values[0], 15.);
+        assert!(values[1].is_finite());
+
+        let mut sensor = sensor();
+        sensor.temperature = None;
+        sensor.abs_humidity = None;
+        for weather in [Some(&weather), None] {
+            let mut values = Vec::new();
+            push_values(&sensor, weather, &mut values);
+            assert!(values[0].is_nan() && values[1].is_nan(), "{:?}", values);
+            assert_eq!(values[2], 0.);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+};
+
+use chrono::NaiveDateTime;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, scaling::sensor_fields, Sensor, SensorLocation};
+
+/// Compass direction the windows of a room face.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Orientation {
+    N,
+    NE,
+    E,
+    SE,
+    S,
+    SW,
+    W,
+    NW,
+}
+
+impl Orientation {
+    fn azimuth(&self) -> f32 {
+        match self {
+            Orientation::N => 0.,
+            Orientation::NE => 45.,
+            Orientation::E => 90.,
+            Orientation::SE => 135.,
+            Orientation::S => 180.,
+            Orientation::SW => 225.,
+            Orientation::W => 270.,
+            Orientation::NW => 315.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ventilation {
+    /// Windows only.
+    Natural,
+    Mechanical,
+    /// Mechanical ventilation and windows that are opened.
+    Hybrid,
+}
+
+/// Physical properties of a room, one row of `rooms_file`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Room {
+    location: SensorLocation,
+    /// m³
+    pub volume: f32,
+    /// Floor area in m².
+    pub area: f32,
+    /// Seats.
+    pub capacity: f32,
+    /// Empty for rooms without outside windows.
+    pub orientation: Option<Orientation>,
+    pub ventilation: Ventilation,
+}
+
+/// Room property a sensor field can be divided by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomMeasure {
+    Volume,
+    Area,
+    Capacity,
+}
+
+/// Sensor field divided by a room property, the `{field}_per_{by}` column,
+/// e.g. `co2_per_volume`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomNormalization {
+    pub field: String,
+    pub by: RoomMeasure,
+}
+
+impl RoomNormalization {
+    pub fn column(&self) -> String {
+        let by = serde_json::to_value(self.by).ok().and_then(|v| v.as_str().map(String::from)).unwrap_or_default();
+        format!("{}_per_{}", self.field, by)
+    }
+}
+
+/// Columns of `room_features`.
+pub static ROOM_COLUMNS: [&str; 8] = [
+    "room_volume",
+    "room_area",
+    "room_capacity",
+    "orientation_sin",
+    "orientation_cos",
+    "ventilation_natural",
+    "ventilation_mechanical",
+    "ventilation_hybrid",
+];
+
+#[derive(Debug, Default)]
+pub struct Rooms {
+    rooms: BTreeMap<SensorLocation, Room>,
+}
+
+impl Rooms {
+    /// Reads `rooms_file` when set, a csv with `location`, `volume`, `area`,
+    /// `capacity`, `orientation` and `ventilation` columns.
+    pub fn new(config: &Config) -> Result<Self, Box<dyn Error>> {
+        let mut rooms = Rooms::default();
+        let path = match &config.rooms_file {
+            Some(path) => path,
+            None => return Ok(rooms),
+        };
+        let mut reader = csv::Reader::from_path(path)?;
+        for row in reader.deserialize() {
+            let room: Room = row?;
+            rooms.rooms.insert(room.location.clone(), room);
+        }
+        Ok(rooms)
+    }
+
+    pub fn get(&self, location: &SensorLocation) -> Option<&Room> {
+        self.rooms.get(location)
+    }
+}
+
+impl Room {
+    fn measure(&self, measure: RoomMeasure) -> f32 {
+        match measure {
+            RoomMeasure::Volume => self.volume,
+            RoomMeasure::Area => self.area,
+            RoomMeasure::Capacity => self.capacity,
+        }
+    }
+
+    /// Values of `ROOM_COLUMNS`. The orientation is encoded as its azimuth on
+    /// the unit circle, rooms without windows get (0, 0).
+    fn push_values(&self, values: &mut Vec<f32>) {
+        let (sin, cos) = match self.orientation {
+            Some(orientation) => orientation.azimuth().to_radians().sin_cos(),
+            None => (0., 0.),
+        };
+        values.extend([self.volume, self.area, self.capacity, sin, cos]);
+        values.extend([Ventilation::Natural, Ventilation::Mechanical, Ventilation::Hybrid].map(|v| {
+            if v == self.ventilation { 1. } else { 0. }
+        }));
+    }
+}
+
+/// Appends the static room features and the normalized fields to the derived
+/// values of every sensor, from the raw readings. Rooms missing from
+/// `rooms_file` get 0 everywhere.
+pub fn add_room_features(sensor_data: &DashMap<NaiveDateTime, Vec<Sensor>>, rooms: &Rooms, config: &Config) {
+    if !config.room_features && config.room_normalizations.is_empty() {
+        return;
+    }
+    let locations: BTreeSet<SensorLocation> =
+        sensor_data.iter().flat_map(|item| item.value().iter().map(|s| s.location.clone()).collect::<Vec<_>>()).collect();
+    let missing = locations.iter().filter(|l| rooms.get(l).is_none() && !config.excluded_locations.contains(l));
+    for location in missing {
+        println!("No metadata for room {:?}, its room features are 0", location);
+    }
+    for mut item in sensor_data.iter_mut() {
+        for sensor in item.value_mut().iter_mut() {
+            let room = rooms.get(&sensor.location);
+            let mut values = Vec::new();
+            if config.room_features {
+                match room {
+                    Some(room) => room.push_values(&mut values),
+                    None => values.extend(ROOM_COLUMNS.map(|_| 0.)),
+                }
+            }
+            for normalization in config.room_normalizations.iter() {
+                let value = sensor_fields(sensor)
+                    .into_iter()
+                    .find(|(name, _)| *name == normalization.field)
+                    .and_then(|(_, value)| value);
+                let measure = room.map(|room| room.measure(normalization.by)).filter(|m| *m > 0.);
+                values.push(value.zip(measure).map(|(value, measure)| value / measure).unwrap_or_default());
+            }
+            sensor.derived.extend(values);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+use super::{non_zero, sketch::Sketch, without_nan, Scaler};
+
+/// Divides by the largest absolute value, keeps zero at zero. NaNs are
+/// ignored, without any values the data is left as it is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaxAbsScaler {
+    max_abs: f32,
+}
+
+impl Scaler for MaxAbsScaler {
+    fn fit(data: &[f32]) -> Self {
+        let max_abs = without_nan(data).iter().map(|v| v.abs()).fold(0., f32::max);
+
+        MaxAbsScaler { max_abs: non_zero(max_abs) }
+    }
+
+    fn fit_sketch(sketch: &mut Sketch) -> Self {
+        if sketch.is_empty() {
+            return MaxAbsScaler { max_abs: 1. };
+        }
+        let max_abs = sketch.min().abs().max(sketch.max().abs()) as f32;
+
+        MaxAbsScaler { max_abs: non_zero(max_abs) }
+    }
+
+    fn transform(&self, value: f32) -> f32 {
+        value / self.max_abs
+    }
+
+    fn inverse_transform(&self, value: f32) -> f32 {
+        value * self.max_abs
+    }
+}
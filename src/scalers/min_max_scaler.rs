@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+use super::{non_zero, sketch::Sketch, without_nan, Scaler};
+
+/// Maps the fitted range onto [0, 1]. NaNs are ignored, without any values
+/// the data is left as it is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MinMaxScaler {
+    min: f32,
+    range: f32,
+}
+
+impl Scaler for MinMaxScaler {
+    fn fit(data: &[f32]) -> Self {
+        let data = without_nan(data);
+        if data.is_empty() {
+            return MinMaxScaler { min: 0., range: 1. };
+        }
+        let min = data.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = data.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+
+        MinMaxScaler { min, range: non_zero(max - min) }
+    }
+
+    fn fit_sketch(sketch: &mut Sketch) -> Self {
+        if sketch.is_empty() {
+            return MinMaxScaler { min: 0., range: 1. };
+        }
+        let (min, max) = (sketch.min() as f32, sketch.max() as f32);
+
+        MinMaxScaler { min, range: non_zero(max - min) }
+    }
+
+    fn transform(&self, value: f32) -> f32 {
+        (value - self.min) / self.range
+    }
+
+    fn inverse_transform(&self, value: f32) -> f32 {
+        value * self.range + self.min
+    }
+}
@@ -1,2 +1,250 @@
 pub mod standard_scaler;
-pub mod robust_scaler;
\ No newline at end of file
+pub mod robust_scaler;
+pub mod min_max_scaler;
+pub mod max_abs_scaler;
+pub mod quantile_scaler;
+pub mod power_transform;
+pub mod sketch;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use self::{
+    max_abs_scaler::MaxAbsScaler,
+    min_max_scaler::MinMaxScaler,
+    power_transform::{BoxCox, Log1p, YeoJohnson},
+    quantile_scaler::QuantileScaler,
+    robust_scaler::RobustScaler,
+    sketch::Sketch,
+    standard_scaler::StandardScaler,
+};
+
+/// A per-feature scaler. Fitted parameters are (de)serialized with serde so
+/// they can be stored next to the data they were used on.
+pub trait Scaler: Serialize + DeserializeOwned {
+    fn fit(data: &[f32]) -> Self;
+    /// Fits from a bounded memory summary of the data instead of all values.
+    fn fit_sketch(sketch: &mut Sketch) -> Self;
+    fn transform(&self, value: f32) -> f32;
+    fn inverse_transform(&self, value: f32) -> f32;
+}
+
+/// Which scaler to fit for a feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScalerKind {
+    Robust,
+    Standard,
+    MinMax,
+    MaxAbs,
+    Quantile,
+    /// Leave the feature as it is.
+    None,
+}
+
+/// Transform applied before the scaler, for skewed features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransformKind {
+    YeoJohnson,
+    /// Only for strictly positive features.
+    BoxCox,
+    Log1p,
+}
+
+/// Any fitted scaler, tagged with its kind when serialized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FittedScaler {
+    Robust(RobustScaler),
+    Standard(StandardScaler),
+    MinMax(MinMaxScaler),
+    MaxAbs(MaxAbsScaler),
+    Quantile(QuantileScaler),
+    YeoJohnson(YeoJohnson),
+    BoxCox(BoxCox),
+    Log1p(Log1p),
+}
+
+impl FittedScaler {
+    /// `quantile_range` is only used by the robust scaler, in percent.
+    pub fn fit(kind: ScalerKind, data: &[f32], quantile_range: (f32, f32)) -> Option<Self> {
+        match kind {
+            ScalerKind::Robust => Some(FittedScaler::Robust(RobustScaler::new(data, quantile_range))),
+            ScalerKind::Standard => Some(FittedScaler::Standard(StandardScaler::fit(data))),
+            ScalerKind::MinMax => Some(FittedScaler::MinMax(MinMaxScaler::fit(data))),
+            ScalerKind::MaxAbs => Some(FittedScaler::MaxAbs(MaxAbsScaler::fit(data))),
+            ScalerKind::Quantile => Some(FittedScaler::Quantile(QuantileScaler::fit(data))),
+            ScalerKind::None => None,
+        }
+    }
+
+    pub fn fit_sketch(kind: ScalerKind, sketch: &mut Sketch, quantile_range: (f32, f32)) -> Option<Self> {
+        match kind {
+            ScalerKind::Robust => Some(FittedScaler::Robust(RobustScaler::from_sketch(sketch, quantile_range))),
+            ScalerKind::Standard => Some(FittedScaler::Standard(StandardScaler::fit_sketch(sketch))),
+            ScalerKind::MinMax => Some(FittedScaler::MinMax(MinMaxScaler::fit_sketch(sketch))),
+            ScalerKind::MaxAbs => Some(FittedScaler::MaxAbs(MaxAbsScaler::fit_sketch(sketch))),
+            ScalerKind::Quantile => Some(FittedScaler::Quantile(QuantileScaler::fit_sketch(sketch))),
+            ScalerKind::None => None,
+        }
+    }
+
+    /// `None` when a Box-Cox transform is asked for data that is not strictly positive.
+    pub fn fit_transform(kind: TransformKind, data: &[f32]) -> Option<Self> {
+        match kind {
+            TransformKind::YeoJohnson => Some(FittedScaler::YeoJohnson(YeoJohnson::fit(data))),
+            TransformKind::BoxCox if data.iter().any(|v| *v <= 0.) => None,
+            TransformKind::BoxCox => Some(FittedScaler::BoxCox(BoxCox::fit(data))),
+            TransformKind::Log1p => Some(FittedScaler::Log1p(Log1p::fit(data))),
+        }
+    }
+
+    pub fn fit_transform_sketch(kind: TransformKind, sketch: &mut Sketch) -> Option<Self> {
+        match kind {
+            TransformKind::YeoJohnson => Some(FittedScaler::YeoJohnson(YeoJohnson::fit_sketch(sketch))),
+            TransformKind::BoxCox if sketch.min() <= 0. => None,
+            TransformKind::BoxCox => Some(FittedScaler::BoxCox(BoxCox::fit_sketch(sketch))),
+            TransformKind::Log1p => Some(FittedScaler::Log1p(Log1p::fit_sketch(sketch))),
+        }
+    }
+
+    pub fn transform(&self, value: f32) -> f32 {
+        match self {
+            FittedScaler::Robust(s) => s.transform(value),
+            FittedScaler::Standard(s) => s.transform(value),
+            FittedScaler::MinMax(s) => s.transform(value),
+            FittedScaler::MaxAbs(s) => s.transform(value),
+            FittedScaler::Quantile(s) => s.transform(value),
+            FittedScaler::YeoJohnson(s) => s.transform(value),
+            FittedScaler::BoxCox(s) => s.transform(value),
+            FittedScaler::Log1p(s) => s.transform(value),
+        }
+    }
+
+    /// Maps a scaled value back to the original units, e.g. for predictions.
+    pub fn inverse_transform(&self, value: f32) -> f32 {
+        match self {
+            FittedScaler::Robust(s) => s.inverse_transform(value),
+            FittedScaler::Standard(s) => s.inverse_transform(value),
+            FittedScaler::MinMax(s) => s.inverse_transform(value),
+            FittedScaler::MaxAbs(s) => s.inverse_transform(value),
+            FittedScaler::Quantile(s) => s.inverse_transform(value),
+            FittedScaler::YeoJohnson(s) => s.inverse_transform(value),
+            FittedScaler::BoxCox(s) => s.inverse_transform(value),
+            FittedScaler::Log1p(s) => s.inverse_transform(value),
+        }
+    }
+}
+
+/// The values a scaler is fitted on, NaNs are ignored like in numpy's
+/// `nan*` functions and sklearn's scalers.
+pub fn without_nan(data: &[f32]) -> Vec<f32> {
+    data.iter().copied().filter(|v| !v.is_nan()).collect()
+}
+
+/// Scale that maps a zero range to 1, so constant features are only shifted.
+pub fn non_zero(scale: f32) -> f32 {
+    if scale == 0. { 1. } else { scale }
+}
+
+/// Quantile `q` (0..=1) of sorted data with linear interpolation between the
+/// closest ranks, numpy's default `percentile` method. Computed in f64 with
+/// numpy's `_lerp` so the result matches numpy on the same (f32) values.
+pub fn quantile(sorted_data: &[f32], q: f64) -> f64 {
+    if sorted_data.is_empty() {
+        return f64::NAN;
+    }
+    let position = q.clamp(0., 1.) * (sorted_data.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = (lower + 1).min(sorted_data.len() - 1);
+    let fraction = position - lower as f64;
+
+    let lower_value = sorted_data[lower] as f64;
+    let upper_value = sorted_data[upper] as f64;
+    let difference = upper_value - lower_value;
+    if fraction >= 0.5 {
+        upper_value - difference * (1. - fraction)
+    } else {
+        lower_value + difference * fraction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static KINDS: [ScalerKind; 5] =
+        [ScalerKind::Robust, ScalerKind::Standard, ScalerKind::MinMax, ScalerKind::MaxAbs, ScalerKind::Quantile];
+    static TRANSFORMS: [TransformKind; 3] = [TransformKind::YeoJohnson, TransformKind::BoxCox, TransformKind::Log1p];
+
+    /// Right skewed and strictly positive, with a missing reading.
+    fn data() -> Vec<f32> {
+        let mut data: Vec<f32> = (1..=200).map(|i| 400. + (i as f32 / 20.).powi(3)).collect();
+        data.push(f32::NAN);
+        data
+    }
+
+    fn sketch(data: &[f32]) -> Sketch {
+        let mut sketch = Sketch::default();
+        data.iter().for_each(|v| sketch.push(*v));
+        sketch
+    }
+
+    fn assert_round_trip(scaler: &FittedScaler, data: &[f32]) {
+        for value in data.iter().filter(|v| !v.is_nan()) {
+            let scaled = scaler.transform(*value);
+            assert!(scaled.is_finite(), "{:?} maps {} to {}", scaler, value, scaled);
+            let back = scaler.inverse_transform(scaled);
+            assert!((back - value).abs() <= 1e-3 * value.abs().max(1.), "{:?}: {} -> {} -> {}", scaler, value, scaled, back);
+        }
+    }
+
+    #[test]
+    fn scalers_round_trip() {
+        let data = data();
+        for kind in KINDS {
+            let fitted = FittedScaler::fit(kind, &data, (25., 75.));
+            assert_round_trip(&fitted.unwrap(), &data);
+            let fitted = FittedScaler::fit_sketch(kind, &mut sketch(&data), (25., 75.));
+            assert_round_trip(&fitted.unwrap(), &data);
+        }
+        assert!(FittedScaler::fit(ScalerKind::None, &data, (25., 75.)).is_none());
+    }
+
+    #[test]
+    fn transforms_round_trip() {
+        let data = data();
+        for kind in TRANSFORMS {
+            assert_round_trip(&FittedScaler::fit_transform(kind, &data).unwrap(), &data);
+            assert_round_trip(&FittedScaler::fit_transform_sketch(kind, &mut sketch(&data)).unwrap(), &data);
+        }
+    }
+
+    #[test]
+    fn without_values_is_identity() {
+        for data in [vec![], vec![f32::NAN, f32::NAN]] {
+            for kind in KINDS {
+                for scaler in [
+                    FittedScaler::fit(kind, &data, (25., 75.)).unwrap(),
+                    FittedScaler::fit_sketch(kind, &mut sketch(&data), (25., 75.)).unwrap(),
+                ] {
+                    assert_eq!(scaler.transform(42.), 42., "{:?}", scaler);
+                    assert_eq!(scaler.inverse_transform(42.), 42., "{:?}", scaler);
+                }
+            }
+        }
+    }
+
+
+    #[test]
+    fn quantile_interpolates_like_numpy() {
+        let sorted = [1., 2., 3., 4.];
+        assert_eq!(quantile(&sorted, 0.), 1.);
+        assert_eq!(quantile(&sorted, 0.25), 1.75);
+        assert_eq!(quantile(&sorted, 0.5), 2.5);
+        assert_eq!(quantile(&sorted, 0.75), 3.25);
+        assert_eq!(quantile(&sorted, 1.), 4.);
+        assert_eq!(quantile(&[3.], 0.3), 3.);
+        assert!(quantile(&[], 0.5).is_nan());
+    }
+}
@@ -0,0 +1,223 @@
+use serde::{Deserialize, Serialize};
+
+use super::{sketch::Sketch, Scaler};
+
+/// Lambdas are searched in this range. sklearn's Brent search is unbounded,
+/// on data that wants a lambda outside of it the two differ.
+static LAMBDA_RANGE: (f64, f64) = (-5., 5.);
+
+/// The transform of sklearn's `PowerTransformer(method="yeo-johnson",
+/// standardize=False)` with the maximum likelihood lambda in `LAMBDA_RANGE`,
+/// not checked against sklearn's `lambdas_`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct YeoJohnson {
+    lambda: f32,
+}
+
+/// The transform of sklearn's `PowerTransformer(method="box-cox",
+/// standardize=False)`, fitted like `YeoJohnson`. Only defined for strictly
+/// positive data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoxCox {
+    lambda: f32,
+}
+
+/// `ln(1 + x)`, nothing to fit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Log1p;
+
+fn yeo_johnson(x: f64, lambda: f64) -> f64 {
+    if x >= 0. {
+        if lambda.abs() < f64::EPSILON {
+            x.ln_1p()
+        } else {
+            ((x + 1.).powf(lambda) - 1.) / lambda
+        }
+    } else if (lambda - 2.).abs() < f64::EPSILON {
+        -(-x).ln_1p()
+    } else {
+        -((-x + 1.).powf(2. - lambda) - 1.) / (2. - lambda)
+    }
+}
+
+fn box_cox(x: f64, lambda: f64) -> f64 {
+    if lambda.abs() < f64::EPSILON {
+        x.ln()
+    } else {
+        (x.powf(lambda) - 1.) / lambda
+    }
+}
+
+/// Weighted variance of `(value, weight)` points.
+fn variance(points: impl Iterator<Item = (f64, f64)> + Clone) -> f64 {
+    let n: f64 = points.clone().map(|(_, w)| w).sum();
+    let mean = points.clone().map(|(v, w)| v * w).sum::<f64>() / n;
+    points.map(|(v, w)| (v - mean).powi(2) * w).sum::<f64>() / n
+}
+
+fn unweighted(data: &[f32]) -> Vec<(f64, f64)> {
+    data.iter().filter(|v| !v.is_nan()).map(|&v| (v as f64, 1.)).collect()
+}
+
+/// Lambda with the highest log likelihood of `(value, weight)` points
+/// (weights are used for sketch centroids), found with a golden section
+/// search as the likelihood is concave in lambda. Constant data has no best
+/// lambda, 1 is returned which leaves the shape as it is.
+fn fit_lambda(points: &[(f64, f64)], transform: fn(f64, f64) -> f64, log_jacobian: fn(f64) -> f64) -> f64 {
+    if points.len() < 2 || variance(points.iter().copied()) == 0. {
+        return 1.;
+    }
+    let n: f64 = points.iter().map(|(_, w)| w).sum();
+    let log_sum: f64 = points.iter().map(|&(x, w)| log_jacobian(x) * w).sum();
+    let log_likelihood = |lambda: f64| {
+        let transformed = points.iter().map(|&(x, w)| (transform(x, lambda), w));
+        (lambda - 1.) * log_sum - n / 2. * variance(transformed).ln()
+    };
+
+    let ratio = (5f64.sqrt() - 1.) / 2.;
+    let (mut low, mut high) = LAMBDA_RANGE;
+
+    while high - low > 1e-6 {
+        let left = high - ratio * (high - low);
+        let right = low + ratio * (high - low);
+        if log_likelihood(left) < log_likelihood(right) {
+            low = left;
+        } else {
+            high = right;
+        }
+    }
+    (low + high) / 2.
+}
+
+impl Scaler for YeoJohnson {
+    fn fit(data: &[f32]) -> Self {
+        let lambda = fit_lambda(&unweighted(data), yeo_johnson, |x| x.signum() * x.abs().ln_1p());
+        YeoJohnson { lambda: lambda as f32 }
+    }
+
+    fn fit_sketch(sketch: &mut Sketch) -> Self {
+        let lambda = fit_lambda(sketch.centroids(), yeo_johnson, |x| x.signum() * x.abs().ln_1p());
+        YeoJohnson { lambda: lambda as f32 }
+    }
+
+    fn transform(&self, value: f32) -> f32 {
+        yeo_johnson(value as f64, self.lambda as f64) as f32
+    }
+
+    fn inverse_transform(&self, value: f32) -> f32 {
+        let (x, lambda) = (value as f64, self.lambda as f64);
+        let original = if x >= 0. {
+            if lambda.abs() < f64::EPSILON {
+                x.exp_m1()
+            } else {
+                (x * lambda + 1.).powf(1. / lambda) - 1.
+            }
+        } else if (lambda - 2.).abs() < f64::EPSILON {
+            -(-x).exp_m1()
+        } else {
+            1. - (-(2. - lambda) * x + 1.).powf(1. / (2. - lambda))
+        };
+        original as f32
+    }
+}
+
+impl Scaler for BoxCox {
+    fn fit(data: &[f32]) -> Self {
+        let lambda = fit_lambda(&unweighted(data), box_cox, f64::ln);
+        BoxCox { lambda: lambda as f32 }
+    }
+
+    fn fit_sketch(sketch: &mut Sketch) -> Self {
+        let lambda = fit_lambda(sketch.centroids(), box_cox, f64::ln);
+        BoxCox { lambda: lambda as f32 }
+    }
+
+    fn transform(&self, value: f32) -> f32 {
+        box_cox(value as f64, self.lambda as f64) as f32
+    }
+
+    fn inverse_transform(&self, value: f32) -> f32 {
+        let (x, lambda) = (value as f64, self.lambda as f64);
+        let original = if lambda.abs() < f64::EPSILON {
+            x.exp()
+        } else {
+            (x * lambda + 1.).powf(1. / lambda)
+        };
+        original as f32
+    }
+}
+
+impl Scaler for Log1p {
+    fn fit(_data: &[f32]) -> Self {
+        Log1p
+    }
+
+    fn fit_sketch(_sketch: &mut Sketch) -> Self {
+        Log1p
+    }
+
+    fn transform(&self, value: f32) -> f32 {
+        value.ln_1p()
+    }
+
+    fn inverse_transform(&self, value: f32) -> f32 {
+        value.exp_m1()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scalers::{FittedScaler, TransformKind};
+
+    /// Right skewed, like CO2 above its outdoor level.
+    fn skewed() -> Vec<f32> {
+        (1..=300).map(|i| (i as f32 / 60.).exp()).collect()
+    }
+
+    /// Lambda with the highest likelihood on a 0.001 grid over `LAMBDA_RANGE`.
+    fn grid_lambda(data: &[f32], transform: fn(f64, f64) -> f64, log_jacobian: fn(f64) -> f64) -> f64 {
+        let points = unweighted(data);
+        let n = points.len() as f64;
+        let log_sum: f64 = points.iter().map(|(x, _)| log_jacobian(*x)).sum();
+        let log_likelihood = |lambda: f64| {
+            let transformed = points.iter().map(|&(x, w)| (transform(x, lambda), w));
+            (lambda - 1.) * log_sum - n / 2. * variance(transformed).ln()
+        };
+        (-5000..=5000)
+            .map(|i| i as f64 / 1000.)
+            .max_by(|a, b| log_likelihood(*a).total_cmp(&log_likelihood(*b)))
+            .unwrap()
+    }
+
+    #[test]
+    fn fitted_lambdas_maximize_the_likelihood() {
+        let data = skewed();
+        let yeo_johnson_lambda = YeoJohnson::fit(&data).lambda as f64;
+        let expected = grid_lambda(&data, yeo_johnson, |x| x.signum() * x.abs().ln_1p());
+        assert!((yeo_johnson_lambda - expected).abs() < 2e-3, "{} != {}", yeo_johnson_lambda, expected);
+        // the skew is pulled in
+        assert!(yeo_johnson_lambda < 1.);
+
+        let box_cox_lambda = BoxCox::fit(&data).lambda as f64;
+        let expected = grid_lambda(&data, box_cox, f64::ln);
+        assert!((box_cox_lambda - expected).abs() < 2e-3, "{} != {}", box_cox_lambda, expected);
+        assert!(box_cox_lambda < 1.);
+    }
+
+    #[test]
+    fn constant_data_keeps_its_shape() {
+        assert_eq!(YeoJohnson::fit(&[3., 3., 3.]).lambda, 1.);
+        assert_eq!(BoxCox::fit(&[3.]).lambda, 1.);
+    }
+
+    #[test]
+    fn box_cox_needs_positive_data() {
+        assert!(FittedScaler::fit_transform(TransformKind::BoxCox, &[0., 1., 2.]).is_none());
+        assert!(FittedScaler::fit_transform(TransformKind::BoxCox, &[-1., 1., 2.]).is_none());
+        let mut sketch = Sketch::default();
+        [0., 1., 2.].iter().for_each(|v| sketch.push(*v));
+        assert!(FittedScaler::fit_transform_sketch(TransformKind::BoxCox, &mut sketch).is_none());
+        assert!(FittedScaler::fit_transform(TransformKind::BoxCox, &[0.5, 1., 2.]).is_some());
+    }
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+
+use super::{quantile, sketch::Sketch, without_nan, Scaler};
+
+/// Same as sklearn's `QuantileTransformer` with a uniform output: values are
+/// mapped onto [0, 1] through their position in the fitted distribution.
+/// NaNs are ignored, without any values the data is left as it is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuantileScaler {
+    /// Values at evenly spaced quantiles, from the minimum to the maximum.
+    quantiles: Vec<f32>,
+}
+
+static MAX_QUANTILES: usize = 1000;
+
+impl QuantileScaler {
+    /// Quantile level of the i-th stored quantile.
+    fn reference(&self, i: usize) -> f32 {
+        i as f32 / (self.quantiles.len().max(2) - 1) as f32
+    }
+}
+
+impl Scaler for QuantileScaler {
+    fn fit(data: &[f32]) -> Self {
+        let mut sorted_data = without_nan(data);
+        sorted_data.sort_by(|a, b| a.total_cmp(b));
+
+        let n_quantiles = MAX_QUANTILES.min(sorted_data.len());
+        let steps = (n_quantiles.max(2) - 1) as f64;
+        let quantiles = (0..n_quantiles)
+            .map(|i| quantile(&sorted_data, i as f64 / steps) as f32)
+            .collect();
+
+        QuantileScaler { quantiles }
+    }
+
+    fn fit_sketch(sketch: &mut Sketch) -> Self {
+        let n_quantiles = MAX_QUANTILES.min(sketch.count() as usize);
+        let steps = (n_quantiles.max(2) - 1) as f64;
+        let quantiles = (0..n_quantiles)
+            .map(|i| sketch.quantile(i as f64 / steps) as f32)
+            .collect();
+
+        QuantileScaler { quantiles }
+    }
+
+    /// Repeated quantiles (e.g. lots of zero precipitation) map to the middle
+    /// of their reference range, like sklearn does by averaging the forward
+    /// and backward interpolation.
+    fn transform(&self, value: f32) -> f32 {
+        let q = &self.quantiles;
+        if q.is_empty() {
+            return value;
+        }
+        if value <= q[0] {
+            return 0.;
+        }
+        if value >= q[q.len() - 1] {
+            return 1.;
+        }
+
+        // first quantile above the value and first quantile not below it
+        let above = q.partition_point(|v| *v <= value);
+        let not_below = q.partition_point(|v| *v < value);
+
+        let forward = self.reference(above - 1)
+            + (value - q[above - 1]) / (q[above] - q[above - 1]) * (self.reference(above) - self.reference(above - 1));
+        let backward = if q[not_below] == value {
+            self.reference(not_below)
+        } else {
+            self.reference(not_below - 1)
+                + (value - q[not_below - 1]) / (q[not_below] - q[not_below - 1])
+                    * (self.reference(not_below) - self.reference(not_below - 1))
+        };
+
+        (forward + backward) / 2.
+    }
+
+    fn inverse_transform(&self, value: f32) -> f32 {
+        let q = &self.quantiles;
+        if q.is_empty() {
+            return value;
+        }
+        let position = value.clamp(0., 1.) * (q.len() - 1) as f32;
+        let lower = position.floor() as usize;
+        let upper = (lower + 1).min(q.len() - 1);
+        let fraction = position - lower as f32;
+
+        q[lower] + fraction * (q[upper] - q[lower])
+    }
+}
@@ -1,38 +1,115 @@
+use serde::{Deserialize, Serialize};
+
+use super::{quantile, sketch::Sketch, without_nan, Scaler};
+
+/// sklearn's default `quantile_range`, in percent.
+pub static DEFAULT_QUANTILE_RANGE: (f32, f32) = (25., 75.);
+
+/// Same as sklearn's `RobustScaler`: centered on the median and scaled by the
+/// distance between the two quantiles of `quantile_range`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RobustScaler {
-    median: f32,
-    iqr: f32,
+    center: f32,
+    scale: f32,
+    quantile_range: (f32, f32),
 }
 
 impl RobustScaler {
-    pub fn new(data: &[f32]) -> Self {
-        let mut sorted_data = data.to_vec();
-        sorted_data.sort_by(|a, b| a.partial_cmp(b).unwrap());
-
-        let n = sorted_data.len();
-        let median = if n % 2 == 0 {
-            (sorted_data[n / 2 - 1] + sorted_data[n / 2]) / 2.0
-        } else {
-            sorted_data[n / 2]
-        };
-
-        let q1 = if n % 4 == 0 {
-            (sorted_data[n / 4 - 1] + sorted_data[n / 4]) / 2.0
-        } else {
-            sorted_data[n / 4]
-        };
-
-        let q3 = if n % 4 == 0 {
-            (sorted_data[3 * n / 4 - 1] + sorted_data[3 * n / 4]) / 2.0
-        } else {
-            sorted_data[3 * n / 4]
-        };
-
-        let iqr = q3 - q1;
-        
-        RobustScaler { median, iqr }
-    }
-
-    pub fn transform(&self, value: f32) -> f32 {
-        (value - self.median) / self.iqr
+    /// NaNs are ignored like in `np.nanpercentile`. A range that is (close
+    /// to) zero, e.g. luminance at night, scales by 1 so the feature is only
+    /// centered. Without any values the scaler leaves the data as it is.
+    pub fn new(data: &[f32], quantile_range: (f32, f32)) -> Self {
+        let mut sorted_data = without_nan(data);
+        sorted_data.sort_by(|a, b| a.total_cmp(b));
+
+        if sorted_data.is_empty() {
+            return RobustScaler { center: 0., scale: 1., quantile_range };
+        }
+
+        let (low, high) = quantile_range;
+        let center = quantile(&sorted_data, 0.5);
+        let scale = quantile(&sorted_data, high as f64 / 100.) - quantile(&sorted_data, low as f64 / 100.);
+        RobustScaler::from_quantiles(center, scale, quantile_range)
+    }
+
+    pub fn from_sketch(sketch: &mut Sketch, quantile_range: (f32, f32)) -> Self {
+        if sketch.is_empty() {
+            return RobustScaler { center: 0., scale: 1., quantile_range };
+        }
+
+        let (low, high) = quantile_range;
+        let center = sketch.quantile(0.5);
+        let scale = sketch.quantile(high as f64 / 100.) - sketch.quantile(low as f64 / 100.);
+        RobustScaler::from_quantiles(center, scale, quantile_range)
+    }
+
+    fn from_quantiles(center: f64, scale: f64, quantile_range: (f32, f32)) -> Self {
+        // sklearn's `_handle_zeros_in_scale`
+        let scale = if scale < 10. * f64::EPSILON { 1. } else { scale };
+
+        RobustScaler { center: center as f32, scale: scale as f32, quantile_range }
+    }
+}
+
+impl Scaler for RobustScaler {
+    fn fit(data: &[f32]) -> Self {
+        RobustScaler::new(data, DEFAULT_QUANTILE_RANGE)
+    }
+
+    fn fit_sketch(sketch: &mut Sketch) -> Self {
+        RobustScaler::from_sketch(sketch, DEFAULT_QUANTILE_RANGE)
+    }
+
+    fn transform(&self, value: f32) -> f32 {
+        (value - self.center) / self.scale
+    }
+
+    fn inverse_transform(&self, value: f32) -> f32 {
+        value * self.scale + self.center
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // center_ and scale_ of sklearn's `RobustScaler(quantile_range=...).fit`
+    // on the same values, i.e. np.nanpercentile with linear interpolation.
+    fn assert_fitted(data: &[f32], quantile_range: (f32, f32), center: f32, scale: f32) {
+        let scaler = RobustScaler::new(data, quantile_range);
+        assert!((scaler.center - center).abs() < 1e-6, "center {} != {}", scaler.center, center);
+        assert!((scaler.scale - scale).abs() < 1e-6, "scale {} != {}", scaler.scale, scale);
+    }
+
+    #[test]
+    fn odd_length() {
+        assert_fitted(&[7., 1., 3., 10., 4.], DEFAULT_QUANTILE_RANGE, 4., 4.);
+    }
+
+    #[test]
+    fn even_length() {
+        assert_fitted(&[2., 8., 4., 6., 10., 1.], DEFAULT_QUANTILE_RANGE, 5., 5.);
+    }
+
+    #[test]
+    fn custom_quantile_range() {
+        let data: Vec<f32> = (1..=10).map(|v| v as f32).collect();
+        assert_fitted(&data, (10., 90.), 5.5, 7.2);
+    }
+
+    #[test]
+    fn zero_range_scales_by_one() {
+        assert_fitted(&[5., 5., 5., 5., 9.], DEFAULT_QUANTILE_RANGE, 5., 1.);
+    }
+
+    #[test]
+    fn nan_is_ignored() {
+        assert_fitted(&[1., f32::NAN, 3., 2., f32::NAN, 4.], DEFAULT_QUANTILE_RANGE, 2.5, 1.5);
+    }
+
+    #[test]
+    fn without_values_is_identity() {
+        assert_fitted(&[], DEFAULT_QUANTILE_RANGE, 0., 1.);
+        assert_fitted(&[f32::NAN], DEFAULT_QUANTILE_RANGE, 0., 1.);
     }
 }
@@ -1,19 +1,49 @@
+use serde::{Deserialize, Serialize};
 
+use super::{non_zero, sketch::Sketch, without_nan, Scaler};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StandardScaler {
     mean: f32,
     std_dev: f32,
 }
 
 impl StandardScaler {
+    /// NaNs are ignored, without any values the data is left as it is.
     pub fn new(data: &[f32]) -> Self {
+        let data = without_nan(data);
+        if data.is_empty() {
+            return StandardScaler { mean: 0., std_dev: 1. };
+        }
         let mean = data.iter().sum::<f32>() / (data.len() as f32);
         let var = data.iter().map(|&value| (value - mean).powi(2)).sum::<f32>() / (data.len() as f32);
-        let std_dev = var.sqrt();
+        let std_dev = non_zero(var.sqrt());
         
         StandardScaler { mean, std_dev }
     }
+}
 
-    pub fn transform(&self, value: f32) -> f32 {
+impl Scaler for StandardScaler {
+    fn fit(data: &[f32]) -> Self {
+        StandardScaler::new(data)
+    }
+
+    /// Welford's running mean and variance.
+    fn fit_sketch(sketch: &mut Sketch) -> Self {
+        if sketch.is_empty() {
+            return StandardScaler { mean: 0., std_dev: 1. };
+        }
+        StandardScaler {
+            mean: sketch.mean() as f32,
+            std_dev: non_zero(sketch.variance().sqrt() as f32),
+        }
+    }
+
+    fn transform(&self, value: f32) -> f32 {
         (value - self.mean) / self.std_dev
     }
-}
\ No newline at end of file
+
+    fn inverse_transform(&self, value: f32) -> f32 {
+        value * self.std_dev + self.mean
+    }
+}
@@ -0,0 +1,646 @@
+use std::{collections::BTreeMap, error::Error, fs::File, path::Path};
+
+use chrono::{NaiveDate, NaiveDateTime, Timelike};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_pickle::{DeOptions, SerOptions};
+
+use crate::{
+    config::{Baseline, Config},
+    features::{derived_columns, raw_derived_columns, UNSCALED_COLUMNS},
+    scalers::{quantile, sketch::Sketch, FittedScaler, ScalerKind, TransformKind},
+    LocationDay,
+    MinuteRecord,
+    Sensor,
+    SensorLocation,
+    WeatherPoint,
+};
+
+/// Fitted steps per field name, applied in order: an optional power transform
+/// and the scaler. Fields configured without either are missing.
+pub type ScalerMap = BTreeMap<String, Vec<FittedScaler>>;
+
+pub static SENSOR_FIELDS: [&str; 8] = [
+    "dew_point", "luminance", "voc_index", "co2", "abs_humidity", "rh", "temperature", "vec_eq_co2",
+];
+
+pub static WEATHER_FIELDS: [&str; 10] = [
+    "temperature", "avg_temperature", "min_temperature", "max_temperature", "rel_humidity",
+    "avg_rel_humidity", "min_rel_humidity", "max_rel_humidity", "precipitation", "wind_speed",
+];
+
+/// Scalers fitted either on the whole dataset or on the training side of one split.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FittedScalers {
+    pub sensor: ScalerMap,
+    pub weather: ScalerMap,
+    /// Sensor scalers per room, only with `scalers_per_location`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sensor_per_location: BTreeMap<SensorLocation, ScalerMap>,
+    /// Scalers of the derived feature columns. With global scaling only of
+    /// the ones computed from raw readings, the rest are derived from
+    /// already scaled values.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub derived: ScalerMap,
+}
+
+impl FittedScalers {
+    /// Sensor scalers of a room, the ones fitted on all rooms if it has none of its own.
+    pub fn sensor_scalers(&self, location: &SensorLocation) -> &ScalerMap {
+        self.sensor_per_location.get(location).unwrap_or(&self.sensor)
+    }
+
+    /// Writes the scalers to `dir` as `scalers.json` and `scalers.pkl`.
+    pub fn save(&self, dir: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+        serde_json::to_writer_pretty(File::create(dir.join("scalers.json"))?, self)?;
+        serde_pickle::to_writer(&mut File::create(dir.join("scalers.pkl"))?, self, SerOptions::new())?;
+        Ok(())
+    }
+
+    /// Reads scalers written by `save`, `.pkl` files as pickle and anything else as json.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Error opening scalers {}: {}", path.display(), e).into()),
+        };
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("pkl") => Ok(serde_pickle::from_reader(file, DeOptions::new())?),
+            _ => Ok(serde_json::from_reader(file)?),
+        }
+    }
+}
+
+pub fn sensor_fields(sensor: &Sensor) -> [(&'static str, Option<f32>); 8] {
+    [
+        ("dew_point", sensor.dew_point),
+        ("luminance", sensor.luminance),
+        ("voc_index", sensor.voc_index),
+        ("co2", sensor.co2),
+        ("abs_humidity", sensor.abs_humidity),
+        ("rh", sensor.rh),
+        ("temperature", sensor.temperature),
+        ("vec_eq_co2", sensor.vec_eq_co2),
+    ]
+}
+
+fn sensor_field_mut<'a>(sensor: &'a mut Sensor, name: &str) -> Option<&'a mut Option<f32>> {
+    match name {
+        "dew_point" => Some(&mut sensor.dew_point),
+        "luminance" => Some(&mut sensor.luminance),
+        "voc_index" => Some(&mut sensor.voc_index),
+        "co2" => Some(&mut sensor.co2),
+        "abs_humidity" => Some(&mut sensor.abs_humidity),
+        "rh" => Some(&mut sensor.rh),
+        "temperature" => Some(&mut sensor.temperature),
+        "vec_eq_co2" => Some(&mut sensor.vec_eq_co2),
+        _ => None,
+    }
+}
+
+pub fn weather_fields(weather: &WeatherPoint) -> [(&'static str, f32); 10] {
+    [
+        ("temperature", weather.temperature),
+        ("avg_temperature", weather.avg_temperature),
+        ("min_temperature", weather.min_temperature),
+        ("max_temperature", weather.max_temperature),
+        ("rel_humidity", weather.rel_humidity),
+        ("avg_rel_humidity", weather.avg_rel_humidity),
+        ("min_rel_humidity", weather.min_rel_humidity),
+        ("max_rel_humidity", weather.max_rel_humidity),
+        ("precipitation", weather.precipitation),
+        ("wind_speed", weather.wind_speed),
+    ]
+}
+
+/// Values of one field collected for fitting: all of them, or only a bounded
+/// memory sketch with `streaming_scalers`. Either way the parsed data itself
+/// stays in memory, only the copy made for fitting is bounded.
+enum Column {
+    Values(Vec<f32>),
+    Sketch(Box<Sketch>),
+}
+
+impl Column {
+    fn new(config: &Config) -> Self {
+        match config.streaming_scalers {
+            true => Column::Sketch(Box::default()),
+            false => Column::Values(Vec::new()),
+        }
+    }
+
+    /// NaN marks a missing value and is skipped.
+    fn push(&mut self, value: f32) {
+        if value.is_nan() {
+            return;
+        }
+        match self {
+            Column::Values(values) => values.push(value),
+            Column::Sketch(sketch) => sketch.push(value),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Column::Values(values) => values.is_empty(),
+            Column::Sketch(sketch) => sketch.is_empty(),
+        }
+    }
+}
+
+type Columns = BTreeMap<&'static str, Column>;
+
+fn push_sensor(columns: &mut Columns, sensor: &Sensor, config: &Config) {
+    for (name, value) in sensor_fields(sensor) {
+        if let Some(value) = value {
+            columns.entry(name).or_insert_with(|| Column::new(config)).push(value);
+        }
+    }
+}
+
+/// The scaler of a field is fitted on the values after its power transform.
+fn fit_columns<K: AsRef<str> + Ord>(
+    columns: BTreeMap<K, Column>,
+    kind: impl Fn(&str) -> (Option<TransformKind>, ScalerKind),
+    config: &Config,
+) -> ScalerMap {
+    columns
+        .into_iter()
+        .filter(|(_, column)| !column.is_empty())
+        .filter_map(|(name, column)| {
+            let name = name.as_ref();
+            let (transform_kind, scaler_kind) = kind(name);
+            let mut steps = Vec::new();
+
+            match column {
+                Column::Values(mut values) => {
+                    if let Some(transform_kind) = transform_kind {
+                        match FittedScaler::fit_transform(transform_kind, &values) {
+                            Some(step) => {
+                                values.iter_mut().for_each(|v| *v = step.transform(*v));
+                                steps.push(step);
+                            },
+                            None => println!("Not transforming {}, {:?} needs strictly positive values", name, transform_kind),
+                        }
+                    }
+                    steps.extend(FittedScaler::fit(scaler_kind, &values, config.robust_quantile_range));
+                },
+                Column::Sketch(mut sketch) => {
+                    if let Some(transform_kind) = transform_kind {
+                        match FittedScaler::fit_transform_sketch(transform_kind, &mut sketch) {
+                            Some(step) => {
+                                *sketch = sketch.map(|v| step.transform(v as f32) as f64);
+                                steps.push(step);
+                            },
+                            None => println!("Not transforming {}, {:?} needs strictly positive values", name, transform_kind),
+                        }
+                    }
+                    steps.extend(FittedScaler::fit_sketch(scaler_kind, &mut sketch, config.robust_quantile_range));
+                },
+            }
+
+            (!steps.is_empty()).then(|| (name.to_string(), steps))
+        })
+        .collect()
+}
+
+fn fit_sensor_columns(columns: Columns, config: &Config) -> ScalerMap {
+    fit_columns(columns, |name| (config.sensor_transforms.get(name).copied(), config.sensor_scaler(name)), config)
+}
+
+/// Everything the scalers are fitted on, filled in one pass over the
+/// minutes without copying them.
+struct FitColumns<'a> {
+    config: &'a Config,
+    sensor: Columns,
+    /// Only with `scalers_per_location`.
+    sensor_per_location: BTreeMap<SensorLocation, Columns>,
+    weather: Columns,
+    derived_names: Vec<String>,
+    derived: Vec<Column>,
+}
+
+impl<'a> FitColumns<'a> {
+    fn new(derived_names: Vec<String>, config: &'a Config) -> Self {
+        FitColumns {
+            config,
+            sensor: Columns::new(),
+            sensor_per_location: BTreeMap::new(),
+            weather: Columns::new(),
+            derived: derived_names.iter().map(|_| Column::new(config)).collect(),
+            derived_names,
+        }
+    }
+
+    fn push_sensor(&mut self, sensor: &Sensor) {
+        push_sensor(&mut self.sensor, sensor, self.config);
+        if self.config.scalers_per_location {
+            push_sensor(self.sensor_per_location.entry(sensor.location.clone()).or_default(), sensor, self.config);
+        }
+    }
+
+    fn push_weather(&mut self, weather: &WeatherPoint) {
+        for (name, value) in weather_fields(weather) {
+            self.weather.entry(name).or_insert_with(|| Column::new(self.config)).push(value);
+        }
+    }
+
+    fn push_derived(&mut self, values: &[f32]) {
+        assert_eq!(
+            values.len(),
+            self.derived_names.len(),
+            "derived values do not match their columns {:?}",
+            self.derived_names
+        );
+        for (column, value) in self.derived.iter_mut().zip(values) {
+            column.push(*value);
+        }
+    }
+
+    /// Derived feature columns all get the `default_scaler`, except for the
+    /// `UNSCALED_COLUMNS`.
+    fn fit(self) -> FittedScalers {
+        let config = self.config;
+        FittedScalers {
+            sensor: fit_sensor_columns(self.sensor, config),
+            weather: fit_columns(
+                self.weather,
+                |name| (config.weather_transforms.get(name).copied(), config.weather_scaler(name)),
+                config,
+            ),
+            sensor_per_location: self
+                .sensor_per_location
+                .into_iter()
+                .map(|(location, columns)| (location, fit_sensor_columns(columns, config)))
+                .collect(),
+            derived: fit_columns(
+                self.derived_names.into_iter().zip(self.derived).collect::<BTreeMap<String, Column>>(),
+                |name| match UNSCALED_COLUMNS.contains(&name) {
+                    true => (None, ScalerKind::None),
+                    false => (None, config.default_scaler),
+                },
+                config,
+            ),
+        }
+    }
+}
+
+fn transform(scalers: &ScalerMap, name: &str, value: f32) -> f32 {
+    match scalers.get(name) {
+        Some(steps) => steps.iter().fold(value, |value, step| step.transform(value)),
+        None => value,
+    }
+}
+
+/// Maps a value scaled with `transform` back to its original units.
+pub fn inverse_transform(scalers: &ScalerMap, name: &str, value: f32) -> f32 {
+    match scalers.get(name) {
+        Some(steps) => steps.iter().rev().fold(value, |value, step| step.inverse_transform(value)),
+        None => value,
+    }
+}
+
+pub fn scale_sensor(sensor: &Sensor, scalers: &ScalerMap) -> Sensor {
+    let scale = |name: &str, value: Option<f32>| value.map(|v| transform(scalers, name, v));
+    Sensor {
+        location: sensor.location.clone(),
+        dew_point: scale("dew_point", sensor.dew_point),
+        luminance: scale("luminance", sensor.luminance),
+        voc_index: scale("voc_index", sensor.voc_index),
+        co2: scale("co2", sensor.co2),
+        abs_humidity: scale("abs_humidity", sensor.abs_humidity),
+        rh: scale("rh", sensor.rh),
+        temperature: scale("temperature", sensor.temperature),
+        vec_eq_co2: scale("vec_eq_co2", sensor.vec_eq_co2),
+        derived: sensor.derived.clone(),
+    }
+}
+
+pub fn scale_weather(weather: &WeatherPoint, scalers: &ScalerMap) -> WeatherPoint {
+    WeatherPoint {
+        temperature: transform(scalers, "temperature", weather.temperature),
+        avg_temperature: transform(scalers, "avg_temperature", weather.avg_temperature),
+        min_temperature: transform(scalers, "min_temperature", weather.min_temperature),
+        max_temperature: transform(scalers, "max_temperature", weather.max_temperature),
+        rel_humidity: transform(scalers, "rel_humidity", weather.rel_humidity),
+        avg_rel_humidity: transform(scalers, "avg_rel_humidity", weather.avg_rel_humidity),
+        min_rel_humidity: transform(scalers, "min_rel_humidity", weather.min_rel_humidity),
+        max_rel_humidity: transform(scalers, "max_rel_humidity", weather.max_rel_humidity),
+        precipitation: transform(scalers, "precipitation", weather.precipitation),
+        wind_speed: transform(scalers, "wind_speed", weather.wind_speed),
+    }
+}
+
+/// Fits one scaler per field on everything that was parsed, in a single pass
+/// over the parsed data once parsing (and the derived features) are done.
+pub fn fit_data_scalers(
+    sensor_data: &DashMap<NaiveDateTime, Vec<Sensor>>,
+    weather_data: &DashMap<NaiveDateTime, WeatherPoint>,
+    config: &Config,
+) -> FittedScalers {
+    let mut columns = FitColumns::new(raw_derived_columns(config), config);
+    for item in sensor_data.iter() {
+        for sensor in item.value().iter() {
+            columns.push_sensor(sensor);
+            columns.push_derived(&sensor.derived);
+        }
+    }
+    for item in weather_data.iter() {
+        columns.push_weather(item.value());
+    }
+    columns.fit()
+}
+
+/// Scales the parsed sensor readings in place, rather than into a copy.
+pub fn scale_sensor_data(
+    data: DashMap<NaiveDateTime, Vec<Sensor>>,
+    scalers: &FittedScalers,
+    config: &Config,
+) -> DashMap<NaiveDateTime, Vec<Sensor>> {
+    let derived_names = raw_derived_columns(config);
+
+    for mut item in data.iter_mut() {
+        for sensor in item.value_mut().iter_mut() {
+            let derived = scale_derived(&sensor.derived, &derived_names, &scalers.derived);
+            *sensor = scale_sensor(sensor, scalers.sensor_scalers(&sensor.location));
+            sensor.derived = derived;
+        }
+    }
+
+    data
+}
+
+pub fn scale_weather_data(
+    data: DashMap<NaiveDateTime, WeatherPoint>,
+    scalers: &ScalerMap,
+) -> DashMap<NaiveDateTime, WeatherPoint> {
+    for mut item in data.iter_mut() {
+        *item.value_mut() = scale_weather(item.value(), scalers);
+    }
+
+    data
+}
+
+/// Fits the scalers of one split on the minutes that make up its training
+/// windows, each minute counted once no matter how many windows contain it.
+pub fn fit_split_scalers(training_days: &[&LocationDay], config: &Config) -> FittedScalers {
+    let mut columns = FitColumns::new(derived_columns(config), config);
+    for (_, sensor, _, weather, derived) in training_days.iter().flat_map(|day| day.distinct_minutes()) {
+        columns.push_sensor(sensor);
+        columns.push_weather(weather);
+        columns.push_derived(derived);
+    }
+    columns.fit()
+}
+
+fn scale_derived(values: &[f32], names: &[String], scalers: &ScalerMap) -> Vec<f32> {
+    values.iter().zip(names).map(|(value, name)| transform(scalers, name, *value)).collect()
+}
+
+pub fn scale_location_day(day: &LocationDay, scalers: &FittedScalers, config: &Config) -> LocationDay {
+    let derived_names = derived_columns(config);
+    let scale_minutes = |minutes: &[MinuteRecord]| -> Vec<MinuteRecord> {
+        minutes
+            .iter()
+            .map(|(ndt, sensor, people, weather, derived)| (
+                *ndt,
+                scale_sensor(sensor, scalers.sensor_scalers(&sensor.location)),
+                people.clone(),
+                scale_weather(weather, &scalers.weather),
+                scale_derived(derived, &derived_names, &scalers.derived),
+            ))
+            .collect()
+    };
+    LocationDay {
+        location: day.location.clone(),
+        date: day.date,
+        day_type: day.day_type,
+        first_window_id: day.first_window_id,
+        windows: day.windows.iter().map(|window| scale_minutes(window)).collect(),
+        horizon: scale_minutes(&day.horizon),
+    }
+}
+
+/// Baseline level per room, day and field, as subtracted by `subtract_baselines`.
+pub type BaselineLevels = BTreeMap<(SensorLocation, NaiveDate, String), f32>;
+
+/// Subtracts the per room and day baseline from the configured fields. Days
+/// without readings in the baseline period have nothing to compare to, their
+/// values of the field are dropped. The subtracted levels are returned for
+/// `restore_reading`.
+pub fn subtract_baselines(
+    data: &DashMap<NaiveDateTime, Vec<Sensor>>,
+    baseline: &Baseline,
+) -> (DashMap<NaiveDateTime, Vec<Sensor>>, BaselineLevels) {
+    let mut readings: BTreeMap<(SensorLocation, NaiveDate, &str), Vec<f32>> = BTreeMap::new();
+    for item in data.iter() {
+        let hour = item.key().hour();
+        if hour < baseline.start_hour || hour >= baseline.end_hour {
+            continue;
+        }
+        for sensor in item.value() {
+            for (name, value) in sensor_fields(sensor) {
+                if let Some(value) = value.filter(|_| baseline.fields.iter().any(|f| f == name)) {
+                    readings.entry((sensor.location.clone(), item.key().date(), name)).or_default().push(value);
+                }
+            }
+        }
+    }
+
+    let levels: BaselineLevels = readings
+        .into_iter()
+        .map(|((location, date, name), mut values)| {
+            values.sort_by(|a, b| a.total_cmp(b));
+            ((location, date, name.to_string()), quantile(&values, 0.5) as f32)
+        })
+        .collect();
+
+    let result = DashMap::new();
+    for item in data.iter() {
+        let date = item.key().date();
+        let sensors = item
+            .value()
+            .iter()
+            .map(|sensor| {
+                let mut sensor = sensor.clone();
+                for name in baseline.fields.iter() {
+                    let level = levels.get(&(sensor.location.clone(), date, name.clone())).copied();
+                    if let Some(value) = sensor_field_mut(&mut sensor, name) {
+                        *value = value.zip(level).map(|(value, level)| value - level);
+                    }
+                }
+                sensor
+            })
+            .collect();
+        result.insert(*item.key(), sensors);
+    }
+
+    (result, levels)
+}
+
+/// Maps a sensor value of a room back to what the sensor read: the room's
+/// scalers are inverted when the value is scaled, and the baseline of its
+/// day is added back.
+pub fn restore_reading(
+    scalers: Option<&FittedScalers>,
+    levels: &BaselineLevels,
+    location: &SensorLocation,
+    ndt: &NaiveDateTime,
+    name: &str,
+    value: f32,
+) -> f32 {
+    let value = match scalers {
+        Some(scalers) => inverse_transform(scalers.sensor_scalers(location), name, value),
+        None => value,
+    };
+    match levels.get(&(location.clone(), ndt.date(), name.to_string())) {
+        Some(level) => value + level,
+        None => value,
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BaselineRow<'a> {
+    location: &'a SensorLocation,
+    date: NaiveDate,
+    field: &'a str,
+    level: f32,
+}
+
+/// Writes the subtracted levels to `baselines.csv`, for mapping predictions
+/// of baseline fields back to readings.
+pub fn write_baselines(levels: &BaselineLevels, out_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(File::create(out_dir.join("baselines.csv"))?);
+    for ((location, date, field), level) in levels.iter() {
+        writer.serialize(BaselineRow { location, date: *date, field, level: *level })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, process};
+
+    use crate::{calendar::DayType, scalers::ScalerKind, testing::{record, series, time}};
+
+    fn day(start: &str, co2: &[f32], windows: &[(usize, usize)], horizon: usize) -> LocationDay {
+        let minutes = series(start, "co2", co2);
+        LocationDay {
+            location: SensorLocation::U11,
+            date: minutes[0].0.date(),
+            day_type: DayType::SchoolDay,
+            first_window_id: 0,
+            windows: windows.iter().map(|(from, to)| minutes[*from..*to].to_vec()).collect(),
+            horizon: minutes[minutes.len() - horizon..].to_vec(),
+        }
+    }
+
+    fn parameter(steps: &[FittedScaler], name: &str) -> f64 {
+        serde_json::to_value(&steps[0]).unwrap()[name].as_f64().unwrap()
+    }
+
+    #[test]
+    fn split_scalers_only_see_training_windows() {
+        // overlapping windows over 400..=409, then a horizon minute far off
+        let mut co2: Vec<f32> = (400..410).map(|v| v as f32).collect();
+        co2.push(5000.);
+        let train = day("2023-01-09 08:00", &co2, &[(0, 6), (2, 10)], 1);
+        let test = day("2023-01-10 08:00", &[2000., 2010., 2020., 2030.], &[(0, 4)], 0);
+
+        let config = Config::default();
+        let scalers = fit_split_scalers(&[&train], &config);
+        // every minute once: median 404.5, 75th - 25th percentile 406.75 - 402.25
+        let co2_scaler = &scalers.sensor["co2"];
+        assert_eq!(parameter(co2_scaler, "center"), 404.5);
+        assert_eq!(parameter(co2_scaler, "scale"), 4.5);
+
+        let scaled = scale_location_day(&test, &scalers, &config);
+        let values: Vec<f32> = scaled.windows[0].iter().map(|(_, sensor, ..)| sensor.co2.unwrap()).collect();
+        let expected: Vec<f32> = [2000., 2010., 2020., 2030.].iter().map(|v: &f32| (v - 404.5) / 4.5).collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn saved_scalers_load_back() {
+        let data: Vec<f32> = (1..=100).map(|i| 400. + (i as f32 / 10.).powi(3)).collect();
+        let kinds = [ScalerKind::Robust, ScalerKind::Standard, ScalerKind::MinMax, ScalerKind::MaxAbs, ScalerKind::Quantile];
+        let transforms = [TransformKind::YeoJohnson, TransformKind::BoxCox, TransformKind::Log1p];
+        let steps: Vec<FittedScaler> = kinds
+            .into_iter()
+            .filter_map(|kind| FittedScaler::fit(kind, &data, (10., 90.)))
+            .chain(transforms.into_iter().filter_map(|kind| FittedScaler::fit_transform(kind, &data)))
+            .collect();
+        assert_eq!(steps.len(), 8);
+        let fields = steps.iter().enumerate().map(|(i, step)| (format!("field_{}", i), vec![step.clone()]));
+        let scalers = FittedScalers {
+            sensor: fields.clone().collect(),
+            weather: fields.clone().collect(),
+            sensor_per_location: BTreeMap::from([(SensorLocation::U11, fields.clone().collect())]),
+            derived: fields.collect(),
+        };
+
+        let dir = std::env::temp_dir().join(format!("scalers-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        scalers.save(&dir).unwrap();
+        for file in ["scalers.json", "scalers.pkl"] {
+            let loaded = FittedScalers::load(&dir.join(file)).unwrap();
+            for (saved, loaded) in [
+                (&scalers.sensor, &loaded.sensor),
+                (&scalers.weather, &loaded.weather),
+                (&scalers.sensor_per_location[&SensorLocation::U11], &loaded.sensor_per_location[&SensorLocation::U11]),
+                (&scalers.derived, &loaded.derived),
+            ] {
+                assert_eq!(saved.keys().collect::<Vec<_>>(), loaded.keys().collect::<Vec<_>>(), "{}", file);
+                for (name, value) in saved.keys().flat_map(|name| data.iter().map(move |value| (name, *value))) {
+                    let scaled = transform(saved, name, value);
+                    assert_eq!(scaled.to_bits(), transform(loaded, name, value).to_bits(), "{} {}", file, name);
+                    assert_eq!(
+                        inverse_transform(saved, name, scaled).to_bits(),
+                        inverse_transform(loaded, name, scaled).to_bits(),
+                        "{} {}",
+                        file,
+                        name
+                    );
+                }
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn baselines_are_subtracted_per_room_before_fitting() {
+        // U11 sits at 400 ppm before school and rises by 10 a minute, U4c at 600 and by 20
+        let data: DashMap<NaiveDateTime, Vec<Sensor>> = DashMap::new();
+        for (location, level, step) in [(SensorLocation::U11, 400., 10.), (SensorLocation::U4c, 600., 20.)] {
+            for minute in 0..5 {
+                let readings = [
+                    (format!("2023-01-09 05:0{}", minute), level),
+                    (format!("2023-01-09 08:0{}", minute), level + step * (minute + 1) as f32),
+                ];
+                for (at, co2) in readings {
+                    let mut sensor = record(&at, &[("co2", co2)], 0).1;
+                    sensor.location = location.clone();
+                    data.entry(time(&at)).or_default().push(sensor);
+                }
+            }
+        }
+        let config = Config { baseline: Some(Baseline::default()), scalers_per_location: true, ..Config::default() };
+        let (data, levels) = subtract_baselines(&data, config.baseline.as_ref().unwrap());
+        assert_eq!(levels[&(SensorLocation::U11, time("2023-01-09 08:00").date(), "co2".to_string())], 400.);
+
+        // five 0s and 1 to 5 steps: median half a step, 75th - 25th percentile 2.75 steps
+        let scalers = fit_data_scalers(&data, &DashMap::new(), &config);
+        for (location, step) in [(SensorLocation::U11, 10.), (SensorLocation::U4c, 20.)] {
+            let co2_scaler = &scalers.sensor_per_location[&location]["co2"];
+            assert_eq!(parameter(co2_scaler, "center"), 0.5 * step);
+            assert_eq!(parameter(co2_scaler, "scale"), 2.75 * step);
+        }
+
+        let at = time("2023-01-09 08:02");
+        let scaled = scale_sensor_data(data, &scalers, &config);
+        let u11 = scaled.get(&at).unwrap().iter().find(|s| s.location == SensorLocation::U11).unwrap().co2.unwrap();
+        assert_eq!(restore_reading(Some(&scalers), &levels, &SensorLocation::U11, &at, "co2", u11), 430.);
+        assert_ne!(restore_reading(Some(&scalers), &levels, &SensorLocation::U4c, &at, "co2", u11), 430.);
+    }
+}
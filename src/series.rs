@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    scaling::{sensor_fields, weather_fields},
+    MinuteRecord,
+};
+
+/// Feature computed over the minute series of one room and day, so nothing
+/// leaks from one day into the next.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SeriesFeature {
+    /// Output column it is computed from, e.g. `co2` or `outside_temperature`.
+    pub field: String,
+    pub kind: SeriesKind,
+    /// Lag, difference distance, window length or EWMA span, in minutes.
+    pub minutes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeriesKind {
+    Lag,
+    /// Change since `minutes` ago.
+    Diff,
+    RollingMean,
+    /// Population standard deviation.
+    RollingStd,
+    RollingMin,
+    RollingMax,
+    /// Least squares slope per minute.
+    RollingSlope,
+    /// Exponentially weighted mean with `alpha = 2 / (minutes + 1)`.
+    Ewma,
+}
+
+impl SeriesFeature {
+    pub fn column(&self) -> String {
+        let kind = serde_json::to_value(self.kind).ok().and_then(|v| v.as_str().map(String::from)).unwrap_or_default();
+        format!("{}_{}_{}", self.field, kind, self.minutes)
+    }
+
+    /// Values for every minute of the series, one value per minute. The
+    /// start of the day has less history than asked for, lags and
+    /// differences reach back to the first minute and rolling windows are
+    /// shorter there, so no value is missing.
+    fn compute(&self, series: &[f32]) -> Vec<f32> {
+        let n = self.minutes.max(1);
+        let start = |i: usize| i.saturating_sub(n - 1);
+
+        match self.kind {
+            SeriesKind::Lag => (0..series.len()).map(|i| series[i.saturating_sub(self.minutes)]).collect(),
+            SeriesKind::Diff => (0..series.len()).map(|i| series[i] - series[i.saturating_sub(self.minutes)]).collect(),
+            SeriesKind::RollingMean => (0..series.len()).map(|i| mean(&series[start(i)..=i])).collect(),
+            SeriesKind::RollingStd => (0..series.len())
+                .map(|i| {
+                    let window = &series[start(i)..=i];
+                    let mean = mean(window);
+                    (window.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / window.len() as f32).sqrt()
+                })
+                .collect(),
+            SeriesKind::RollingMin => (0..series.len())
+                .map(|i| series[start(i)..=i].iter().copied().fold(f32::INFINITY, f32::min))
+                .collect(),
+            SeriesKind::RollingMax => (0..series.len())
+                .map(|i| series[start(i)..=i].iter().copied().fold(f32::NEG_INFINITY, f32::max))
+                .collect(),
+            SeriesKind::RollingSlope => (0..series.len()).map(|i| slope(&series[start(i)..=i])).collect(),
+            SeriesKind::Ewma => {
+                let alpha = 2. / (n as f32 + 1.);
+                let mut current = series.first().copied().unwrap_or_default();
+                series
+                    .iter()
+                    .map(|v| {
+                        current = alpha * v + (1. - alpha) * current;
+                        current
+                    })
+                    .collect()
+            },
+        }
+    }
+}
+
+fn mean(values: &[f32]) -> f32 {
+    values.iter().sum::<f32>() / values.len() as f32
+}
+
+fn slope(values: &[f32]) -> f32 {
+    if values.len() < 2 {
+        return 0.;
+    }
+    let x_mean = (values.len() - 1) as f32 / 2.;
+    let y_mean = mean(values);
+    let (covariance, variance) = values.iter().enumerate().fold((0., 0.), |(c, v), (x, y)| {
+        let dx = x as f32 - x_mean;
+        (c + dx * (y - y_mean), v + dx * dx)
+    });
+    covariance / variance
+}
+
+/// Value of an output column at one minute, `None` when the sensor did not report it.
+pub fn field_value((_, sensor, _, weather, _): &MinuteRecord, field: &str) -> Option<f32> {
+    if let Some((_, value)) = sensor_fields(sensor).into_iter().find(|(name, _)| *name == field) {
+        return value;
+    }
+    let field = if field == "outside_temperature" { "temperature" } else { field };
+    weather_fields(weather).into_iter().find(|(name, _)| *name == field).map(|(_, value)| value)
+}
+
+/// Column of a field over a day. Missing readings repeat the last known value
+/// (the first known one at the start of the day), a field that is never
+/// reported stays 0.
+pub fn day_series(records: &[MinuteRecord], field: &str) -> Vec<f32> {
+    let values: Vec<Option<f32>> = records.iter().map(|record| field_value(record, field)).collect();
+    let mut last = values.iter().flatten().next().copied().unwrap_or_default();
+    values
+        .into_iter()
+        .map(|value| {
+            last = value.unwrap_or(last);
+            last
+        })
+        .collect()
+}
+
+/// Minutes of the records since the first one, short gaps leave minutes out.
+pub fn minute_offsets(records: &[MinuteRecord]) -> Vec<usize> {
+    let start = match records.first() {
+        Some((ndt, ..)) => *ndt,
+        None => return vec![],
+    };
+    records.iter().map(|(ndt, ..)| (*ndt - start).num_minutes().max(0) as usize).collect()
+}
+
+/// `day_series` with one value for every minute from the first record to
+/// the last, minutes of a gap repeat the last reading before it.
+fn minute_series(records: &[MinuteRecord], offsets: &[usize], field: &str) -> Vec<f32> {
+    let values = day_series(records, field);
+    let mut series = Vec::with_capacity(offsets.last().map_or(0, |last| last + 1));
+    for (offset, value) in offsets.iter().zip(values) {
+        while series.len() < *offset {
+            series.push(series.last().copied().unwrap_or(value));
+        }
+        series.push(value);
+    }
+    series
+}
+
+/// Appends the values of `series_features` to the feature values of every
+/// minute. Days have to be sorted by time. Lags and windows are counted in
+/// minutes, not rows, so they reach back as far over a gap as elsewhere.
+pub fn add_series_features(
+    mut days: HashMap<NaiveDate, Vec<MinuteRecord>>,
+    config: &Config,
+) -> HashMap<NaiveDate, Vec<MinuteRecord>> {
+    if config.series_features.is_empty() {
+        return days;
+    }
+    for records in days.values_mut() {
+        let offsets = minute_offsets(records);
+        let mut series: HashMap<&str, Vec<f32>> = HashMap::new();
+        for feature in config.series_features.iter() {
+            let values = series
+                .entry(feature.field.as_str())
+                .or_insert_with(|| minute_series(records, &offsets, &feature.field));
+            let computed = feature.compute(values);
+            for (record, offset) in records.iter_mut().zip(offsets.iter()) {
+                record.4.push(computed[*offset]);
+            }
+        }
+    }
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::series;
+
+    fn feature(kind: SeriesKind, minutes: usize) -> SeriesFeature {
+        SeriesFeature { field: "co2".to_string(), kind, minutes }
+    }
+
+    fn computed(records: Vec<MinuteRecord>, features: Vec<SeriesFeature>) -> Vec<Vec<f32>> {
+        let config = Config { series_features: features, ..Config::default() };
+        let days = add_series_features(HashMap::from([(records[0].0.date(), records)]), &config);
+        days.into_values().flatten().map(|(.., derived)| derived).collect()
+    }
+
+    #[test]
+    fn day_start_has_shorter_history() {
+        let records = series("2023-01-09 08:00", "co2", &[400., 410., 430., 460.]);
+        let features = vec![
+            feature(SeriesKind::Lag, 2),
+            feature(SeriesKind::Diff, 2),
+            feature(SeriesKind::RollingMean, 3),
+            feature(SeriesKind::RollingMax, 3),
+        ];
+        assert_eq!(
+            computed(records, features),
+            vec![
+                vec![400., 0., 400., 400.],
+                vec![400., 10., 405., 410.],
+                vec![400., 30., 1240. / 3., 430.],
+                vec![410., 50., 1300. / 3., 460.],
+            ]
+        );
+    }
+
+    #[test]
+    fn gaps_count_as_minutes() {
+        // 08:02 and 08:03 are missing
+        let mut records = series("2023-01-09 08:00", "co2", &[400., 410., 440., 450., 460.]);
+        for record in records[2..].iter_mut() {
+            record.0 += chrono::Duration::minutes(2);
+        }
+        let features = vec![feature(SeriesKind::Lag, 3), feature(SeriesKind::RollingMean, 3), feature(SeriesKind::Diff, 1)];
+        let values = computed(records, features);
+
+        // 08:04 reaches back to 08:01, the gap minutes repeat 410
+        assert_eq!(values[2], vec![410., 420., 30.]);
+        // 08:05 to 08:02, inside the gap
+        assert_eq!(values[3], vec![410., 1300. / 3., 10.]);
+        assert_eq!(values[4], vec![410., 450., 10.]);
+    }
+}
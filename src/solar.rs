@@ -0,0 +1,165 @@
+use std::f32::consts::PI;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Timelike};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, Sensor};
+
+/// Columns of the solar features.
+pub static SOLAR_COLUMNS: [&str; 4] =
+    ["solar_elevation", "solar_azimuth_sin", "solar_azimuth_cos", "clear_sky_irradiance"];
+
+/// Where the school is and which clock the timestamps are in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Site {
+    /// Degrees north.
+    pub latitude: f32,
+    /// Degrees east.
+    pub longitude: f32,
+    /// Hours the timestamps are ahead of UTC outside of summer time.
+    pub utc_offset: i64,
+    /// The timestamps follow EU summer time, one more hour from the last
+    /// Sunday of March to the last Sunday of October.
+    pub summer_time: bool,
+}
+
+impl Default for Site {
+    fn default() -> Self {
+        // Ajdovščina. The sensor exports are in UTC (`Z` timestamps), set
+        // `utc_offset` 1 and `summer_time` for data in Central European Time.
+        Site {
+            latitude: 45.887,
+            longitude: 13.910,
+            utc_offset: 0,
+            summer_time: false,
+        }
+    }
+}
+
+fn last_sunday(year: i32, month: u32) -> Option<NaiveDate> {
+    let last = NaiveDate::from_ymd_opt(year, month + 1, 1)?.pred_opt()?;
+    Some(last - Duration::days(last.weekday().num_days_from_sunday() as i64))
+}
+
+impl Site {
+    /// UTC time of a local timestamp. Summer time starts and ends at 01:00
+    /// UTC, the repeated hour in October is read as summer time.
+    fn to_utc(&self, local: &NaiveDateTime) -> NaiveDateTime {
+        let standard = *local - Duration::hours(self.utc_offset);
+        let year = local.year();
+        let summer = match (last_sunday(year, 3), last_sunday(year, 10)) {
+            (Some(start), Some(end)) if self.summer_time => {
+                let switch = |date: NaiveDate| date.and_hms_opt(1, 0, 0).unwrap_or_default();
+                standard >= switch(start) && standard < switch(end) + Duration::hours(1)
+            },
+            _ => false,
+        };
+        if summer { standard - Duration::hours(1) } else { standard }
+    }
+
+    /// Solar elevation and azimuth (clockwise from north) in degrees, after
+    /// NOAA's general solar position equations, good to about a degree.
+    pub fn position(&self, local: &NaiveDateTime) -> (f32, f32) {
+        let utc = self.to_utc(local);
+        let hour = utc.hour() as f32 + utc.minute() as f32 / 60.;
+        let days_in_year = if utc.date().leap_year() { 366. } else { 365. };
+        let gamma = 2. * PI / days_in_year * (utc.ordinal0() as f32 + (hour - 12.) / 24.);
+
+        let equation_of_time = 229.18
+            * (0.000075 + 0.001868 * gamma.cos()
+                - 0.032077 * gamma.sin()
+                - 0.014615 * (2. * gamma).cos()
+                - 0.040849 * (2. * gamma).sin());
+        let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin() - 0.006758 * (2. * gamma).cos()
+            + 0.000907 * (2. * gamma).sin()
+            - 0.002697 * (3. * gamma).cos()
+            + 0.00148 * (3. * gamma).sin();
+
+        let true_solar_minutes = hour * 60. + equation_of_time + 4. * self.longitude;
+        let hour_angle = (true_solar_minutes / 4. - 180.).to_radians();
+        let latitude = self.latitude.to_radians();
+
+        let cos_zenith = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+        let elevation = 90. - cos_zenith.clamp(-1., 1.).acos().to_degrees();
+        let azimuth = hour_angle
+            .sin()
+            .atan2(hour_angle.cos() * latitude.sin() - declination.tan() * latitude.cos())
+            .to_degrees()
+            + 180.;
+        (elevation, azimuth.rem_euclid(360.))
+    }
+}
+
+/// Global horizontal irradiance under a clear sky in W/m², Haurwitz model.
+pub fn clear_sky_irradiance(elevation: f32) -> f32 {
+    let cos_zenith = elevation.to_radians().sin();
+    if cos_zenith <= 0. {
+        return 0.;
+    }
+    1098. * cos_zenith * (-0.059 / cos_zenith).exp()
+}
+
+/// Appends the values of `SOLAR_COLUMNS` to the derived values of every sensor.
+pub fn add_solar_features(sensor_data: &DashMap<NaiveDateTime, Vec<Sensor>>, config: &Config) {
+    let site = match &config.solar {
+        Some(site) => site,
+        None => return,
+    };
+    for mut item in sensor_data.iter_mut() {
+        let (elevation, azimuth) = site.position(item.key());
+        let azimuth = azimuth.to_radians();
+        let values = [elevation, azimuth.sin(), azimuth.cos(), clear_sky_irradiance(elevation)];
+        for sensor in item.value_mut().iter_mut() {
+            sensor.derived.extend(values);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::time;
+
+    /// Elevation and azimuth from the Astronomical Almanac's algorithm
+    /// (Michalsky 1988, without refraction) for the default site.
+    static REFERENCE: [(&str, f32, f32); 4] = [
+        ("2023-06-21 11:06", 67.548, 179.933),
+        ("2023-01-09 08:00", 9.358, 135.908),
+        ("2023-03-20 14:30", 26.769, 238.438),
+        ("2023-01-09 22:00", -62.057, 322.590),
+    ];
+
+    #[test]
+    fn position_matches_reference() {
+        let site = Site::default();
+        for (at, elevation, azimuth) in REFERENCE {
+            let (e, a) = site.position(&time(at));
+            assert!((e - elevation).abs() < 0.5, "{} elevation {} not {}", at, e, elevation);
+            assert!((a - azimuth).abs() < 1., "{} azimuth {} not {}", at, a, azimuth);
+        }
+    }
+
+    #[test]
+    fn local_time_is_shifted_to_utc() {
+        let utc = Site::default();
+        let local = Site { utc_offset: 1, summer_time: true, ..Site::default() };
+        // CET in winter, CEST in summer
+        assert_eq!(local.position(&time("2023-01-09 09:00")), utc.position(&time("2023-01-09 08:00")));
+        assert_eq!(local.position(&time("2023-06-21 13:06")), utc.position(&time("2023-06-21 11:06")));
+    }
+
+    #[test]
+    fn no_irradiance_at_night() {
+        let site = Site::default();
+        let (elevation, _) = site.position(&time("2023-01-09 22:00"));
+        assert_eq!(clear_sky_irradiance(elevation), 0.);
+        assert_eq!(clear_sky_irradiance(0.), 0.);
+        assert_eq!(clear_sky_irradiance(-0.1), 0.);
+
+        let (elevation, _) = site.position(&time("2023-06-21 11:06"));
+        let noon = clear_sky_irradiance(elevation);
+        assert!((900. ..1000.).contains(&noon), "{}", noon);
+    }
+}
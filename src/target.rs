@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, series::field_value, MinuteRecord};
+
+/// What the last column says about the counted people.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum OccupancyTarget {
+    /// `people`, the count itself for regression.
+    #[default]
+    Count,
+    /// `occupied`, 1 with at least `threshold` people in the room, else 0.
+    Binary { threshold: i32 },
+    /// `occupancy_class`, the number of `edges` (ascending) the count reaches:
+    /// edges `[1, 10, 20]` give 0 for empty, 1 for 1-9, 2 for 10-19 and 3 from 20 up.
+    Bins { edges: Vec<i32> },
+}
+
+impl OccupancyTarget {
+    pub fn column(&self) -> &'static str {
+        match self {
+            OccupancyTarget::Count => "people",
+            OccupancyTarget::Binary { .. } => "occupied",
+            OccupancyTarget::Bins { .. } => "occupancy_class",
+        }
+    }
+
+    /// Class of a count, the count itself for `Count`.
+    pub fn class(&self, people: i32) -> i32 {
+        match self {
+            OccupancyTarget::Count => people,
+            OccupancyTarget::Binary { threshold } => (people >= *threshold) as i32,
+            OccupancyTarget::Bins { edges } => edges.iter().filter(|edge| people >= **edge).count() as i32,
+        }
+    }
+
+    pub fn is_classification(&self) -> bool {
+        !matches!(self, OccupancyTarget::Count)
+    }
+}
+
+/// Where the target columns are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetPosition {
+    /// After all features, what the loaders expect.
+    #[default]
+    Last,
+    /// Right after `window_id`.
+    First,
+}
+
+/// A column to predict: `people` (as `occupancy_target` says), a sensor
+/// field or a weather field, `horizon` minutes after the row's minute.
+/// Sensor and weather targets are in the same scaled units as their features.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Target {
+    pub field: String,
+    #[serde(default)]
+    pub horizon: usize,
+}
+
+impl Target {
+    pub fn people() -> Self {
+        Target { field: "people".to_string(), horizon: 0 }
+    }
+
+    /// `people` (or the occupancy class column) and `target_{field}` for the
+    /// rest, with `_in_{horizon}` for forecasts, e.g. `target_co2_in_30`.
+    pub fn column(&self, occupancy: &OccupancyTarget) -> String {
+        let base = match self.field.as_str() {
+            "people" => occupancy.column().to_string(),
+            field => format!("target_{}", field),
+        };
+        match self.horizon {
+            0 => base,
+            horizon => format!("{}_in_{}", base, horizon),
+        }
+    }
+}
+
+/// Values of the `targets` for one minute. `minutes` holds every minute of
+/// the day from the first window on, windows end early enough for the
+/// longest horizon to stay inside it. Like `series::day_series` a reading
+/// missing at the future minute (a short gap or a field the sensor did not
+/// report) takes the last one before it, it is NaN when there is none yet.
+pub fn target_values(
+    record: &MinuteRecord,
+    minutes: &BTreeMap<NaiveDateTime, &MinuteRecord>,
+    config: &Config,
+) -> Vec<f32> {
+    config
+        .targets
+        .iter()
+        .map(|target| {
+            let future = record.0 + Duration::minutes(target.horizon as i64);
+            let mut known = minutes.range(..=future).rev().map(|(_, record)| *record);
+            let value = match target.field.as_str() {
+                "people" => known.next().map(|future| config.occupancy_target.class(future.2.people) as f32),
+                field => known.find_map(|future| field_value(future, field)),
+            };
+            value.unwrap_or(f32::NAN)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        calendar::DayType,
+        generate_windows,
+        manifest::summarize_folds,
+        restructure_data_to_output,
+        testing::{record, series},
+        LocationDay,
+        SensorLocation,
+        TargetRow,
+    };
+
+    fn target(field: &str, horizon: usize) -> Target {
+        Target { field: field.to_string(), horizon }
+    }
+
+    /// Rows of one hour of CO2 readings 1000, 1001, ... from 08:00 in windows of 10 minutes.
+    fn rows(config: &Config) -> Vec<TargetRow> {
+        let co2: Vec<f32> = (0..60).map(|minute| 1000. + minute as f32).collect();
+        let records = series("2023-01-09 08:00", "co2", &co2);
+        let date = records[0].0.date();
+        let horizon = config.targets.iter().map(|target| target.horizon).max().unwrap_or_default();
+        let mut windowed = generate_windows(&HashMap::from([(date, records)]), 10, horizon);
+        let (windows, horizon) = windowed.remove(&date).unwrap();
+        let day = LocationDay {
+            location: SensorLocation::U11,
+            date,
+            day_type: DayType::SchoolDay,
+            first_window_id: 0,
+            windows,
+            horizon,
+        };
+        restructure_data_to_output(vec![day], config).remove(0).rows
+    }
+
+    /// Minute after 08:00 of a row, read back from its CO2 feature.
+    fn row_minute(row: &TargetRow, config: &Config) -> usize {
+        let index = TargetRow::header(config).iter().position(|name| name == "co2").unwrap() - 1;
+        (row.values[index] - 1000.) as usize
+    }
+
+    #[test]
+    fn targets_come_from_their_horizon() {
+        for horizon in [0, 30] {
+            let config = Config { targets: vec![target("co2", horizon)], ..Config::default() };
+            let rows = rows(&config);
+            // the last window ends `horizon` minutes before the hour does
+            assert_eq!(rows.len(), (60 - 10 - horizon) * 10);
+            assert_eq!(rows.last().unwrap().window_id, (60 - 10 - horizon) as i32 - 1);
+            for row in rows.iter() {
+                let minute = row_minute(row, &config);
+                assert_eq!(*row.values.last().unwrap(), 1000. + (minute + horizon) as f32, "minute {}", minute);
+            }
+            // the last row of the last window forecasts a minute after all windows
+            let last = row_minute(rows.last().unwrap(), &config);
+            assert_eq!(last + horizon, 58);
+        }
+    }
+
+    #[test]
+    fn missing_targets_take_the_last_reading() {
+        let mut records = series("2023-01-09 08:00", "co2", &[500., 510., 520.]);
+        records[2].1.co2 = None;
+        records.remove(1);
+        let minutes: BTreeMap<NaiveDateTime, &MinuteRecord> = records.iter().map(|record| (record.0, record)).collect();
+        let config = Config {
+            targets: vec![target("co2", 0), target("co2", 1), target("co2", 2), target("temperature", 0)],
+            ..Config::default()
+        };
+
+        // 08:01 is a gap, 08:02 has no CO2 and nothing reported a temperature
+        let values = target_values(&records[0], &minutes, &config);
+        assert_eq!(values[..3], [500., 500., 500.]);
+        assert!(values[3].is_nan());
+    }
+
+    #[test]
+    fn target_columns_follow_their_position() {
+        let targets = vec![target("people", 0), target("co2", 30), target("outside_temperature", 0)];
+        let names = ["people", "target_co2_in_30", "target_outside_temperature"];
+        for position in [TargetPosition::First, TargetPosition::Last] {
+            let config = Config { targets: targets.clone(), target_position: position, ..Config::default() };
+            let header = TargetRow::header(&config);
+            assert_eq!(header[0], "window_id");
+            let columns = match position {
+                TargetPosition::First => &header[1..4],
+                TargetPosition::Last => &header[header.len() - 3..],
+            };
+            assert_eq!(columns, names);
+            assert_eq!(TargetRow::target_columns(&config), names);
+
+            let row = &rows(&config)[0];
+            assert_eq!(row.values.len() + 1, header.len());
+            let values = match position {
+                TargetPosition::First => &row.values[..3],
+                TargetPosition::Last => &row.values[row.values.len() - 3..],
+            };
+            // window 0 starts at 08:00, nobody is counted and it is 0 outside
+            assert_eq!(values, [0., 1030., 0.]);
+        }
+    }
+
+    #[test]
+    fn counts_fall_into_bins() {
+        let bins = OccupancyTarget::Bins { edges: vec![1, 10, 20] };
+        let counts = [0, 1, 9, 10, 19, 20, 21, 150];
+        let classes: Vec<i32> = counts.iter().map(|people| bins.class(*people)).collect();
+        assert_eq!(classes, [0, 1, 1, 2, 2, 3, 3, 3]);
+        assert_eq!(bins.column(), "occupancy_class");
+
+        let binary = OccupancyTarget::Binary { threshold: 5 };
+        assert_eq!(counts.map(|people| binary.class(people)), [0, 0, 1, 1, 1, 1, 1, 1]);
+        assert_eq!(OccupancyTarget::Count.class(21), 21);
+        assert!(!OccupancyTarget::Count.is_classification());
+
+        // every row of every window is counted once per window it is in
+        let minutes: Vec<MinuteRecord> = [0, 5, 12, 30]
+            .iter()
+            .enumerate()
+            .map(|(minute, people)| record(&format!("2023-01-09 08:0{}", minute), &[], *people))
+            .collect();
+        let day = LocationDay {
+            location: SensorLocation::U11,
+            date: minutes[0].0.date(),
+            day_type: DayType::SchoolDay,
+            first_window_id: 0,
+            windows: vec![minutes[..3].to_vec(), minutes[1..].to_vec()],
+            horizon: vec![],
+        };
+        let config = Config { occupancy_target: bins, ..Config::default() };
+        let summaries = summarize_folds(&[vec![day]], &config);
+        assert_eq!(summaries[0].classes, BTreeMap::from([(0, 1), (1, 2), (2, 2), (3, 1)]));
+    }
+}
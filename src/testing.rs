@@ -0,0 +1,62 @@
+//! Records for unit tests.
+
+use chrono::NaiveDateTime;
+
+use crate::{MinuteRecord, SensedPeople, Sensor, SensorLocation, WeatherPoint};
+
+pub fn time(value: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap()
+}
+
+/// A minute of `U11` with only the given readings, `outside_temperature`
+/// sets the weather temperature.
+pub fn record(at: &str, readings: &[(&str, f32)], people: i32) -> MinuteRecord {
+    let mut sensor = Sensor {
+        location: SensorLocation::U11,
+        dew_point: None,
+        luminance: None,
+        voc_index: None,
+        co2: None,
+        abs_humidity: None,
+        rh: None,
+        temperature: None,
+        vec_eq_co2: None,
+        derived: vec![],
+    };
+    let mut weather = WeatherPoint {
+        temperature: 0.,
+        avg_temperature: 0.,
+        min_temperature: 0.,
+        max_temperature: 0.,
+        rel_humidity: 0.,
+        avg_rel_humidity: 0.,
+        min_rel_humidity: 0.,
+        max_rel_humidity: 0.,
+        precipitation: 0.,
+        wind_speed: 0.,
+    };
+    for (field, value) in readings.iter().copied() {
+        match field {
+            "co2" => sensor.co2 = Some(value),
+            "temperature" => sensor.temperature = Some(value),
+            "rh" => sensor.rh = Some(value),
+            "outside_temperature" => weather.temperature = value,
+            _ => panic!("no test field {}", field),
+        }
+    }
+    let people = SensedPeople { sensor_location: SensorLocation::U11, people, scheduled: people > 0 };
+    (time(at), sensor, people, weather, vec![])
+}
+
+/// Consecutive minutes from `start` with one `field` value each.
+pub fn series(start: &str, field: &str, values: &[f32]) -> Vec<MinuteRecord> {
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let mut record = record(start, &[(field, *value)], 0);
+            record.0 += chrono::Duration::minutes(i as i64);
+            record
+        })
+        .collect()
+}
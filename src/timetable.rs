@@ -0,0 +1,46 @@
+use chrono::{NaiveDateTime, NaiveTime, Timelike};
+
+/// Start (hour, minute), length and lesson length in minutes of every
+/// timetable slot, indexed by slot number. The rest of a slot after its
+/// lesson is the break. Slots follow each other without gaps.
+pub static SLOTS: [(u32, u32, i64, i64); 9] = [
+    (7, 30, 30, 30),
+    (8, 0, 50, 45),
+    (8, 50, 50, 45),
+    // followed by the long break for the school meal
+    (9, 40, 70, 45),
+    (10, 50, 50, 45),
+    (11, 40, 50, 45),
+    (12, 30, 50, 45),
+    (13, 20, 50, 45),
+    (14, 10, 45, 45),
+];
+
+/// Where in the school rhythm a minute is.
+pub struct SlotPosition {
+    pub slot: usize,
+    /// Minutes since the slot started.
+    pub elapsed: i64,
+    /// Minutes until the slot ends.
+    pub remaining: i64,
+    pub is_break: bool,
+}
+
+pub fn slot_start(slot: usize) -> Option<(NaiveTime, i64)> {
+    let (hour, minute, duration, _) = SLOTS.get(slot)?;
+    NaiveTime::from_hms_opt(*hour, *minute, 0).map(|start| (start, *duration))
+}
+
+/// `None` outside of the timetable.
+pub fn slot_position(ndt: &NaiveDateTime) -> Option<SlotPosition> {
+    let minute = (ndt.time().num_seconds_from_midnight() / 60) as i64;
+    SLOTS.iter().enumerate().find_map(|(slot, (hour, start, duration, lesson))| {
+        let elapsed = minute - (hour * 60 + start) as i64;
+        (0..*duration).contains(&elapsed).then_some(SlotPosition {
+            slot,
+            elapsed,
+            remaining: duration - elapsed,
+            is_break: elapsed >= *lesson,
+        })
+    })
+}
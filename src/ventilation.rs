@@ -0,0 +1,228 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs::File,
+    path::Path,
+};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+
+use crate::{
+    config::Config,
+    scaling::{inverse_transform, restore_reading, BaselineLevels, FittedScalers},
+    series::{day_series, minute_offsets},
+    MinuteRecord,
+    SensorLocation,
+};
+
+/// Column of the event flag, see `detect_events`.
+pub static VENTILATION_COLUMN: &str = "ventilation_event";
+
+/// One window opening, in the units the sensors read, with any `baseline`
+/// added back.
+#[derive(Debug, Clone, Serialize)]
+pub struct VentilationEvent {
+    pub location: SensorLocation,
+    pub date: NaiveDate,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub minutes: usize,
+    pub co2_start: f32,
+    pub co2_end: f32,
+    pub temperature_start: f32,
+    pub temperature_end: f32,
+    pub outside_temperature: f32,
+}
+
+/// Minute series of a field in the units it was measured in. With global
+/// scaling the records are already scaled and are mapped back, subtracted
+/// baselines are added back.
+fn raw_series(
+    records: &[MinuteRecord],
+    field: &str,
+    location: &SensorLocation,
+    scalers: Option<&FittedScalers>,
+    levels: &BaselineLevels,
+) -> Vec<f32> {
+    let series = day_series(records, field);
+    if field == "outside_temperature" {
+        return match scalers {
+            Some(scalers) => series.into_iter().map(|value| inverse_transform(&scalers.weather, "temperature", value)).collect(),
+            None => series,
+        };
+    }
+    series
+        .into_iter()
+        .zip(records)
+        .map(|(value, (ndt, ..))| restore_reading(scalers, levels, location, ndt, field, value))
+        .collect()
+}
+
+/// Flags the minutes of a room's day in which a window is open: CO2 falls
+/// faster than occupants leaving would explain and, when it is cold enough
+/// outside for it to show, the room cools down with it. A minute is flagged
+/// once the drop has lasted `min_minutes`, so the flag only depends on the
+/// past, the reported events start where the drop started. Drops and
+/// durations are measured in minutes, over a short gap as well.
+pub fn detect_events(
+    mut days: HashMap<NaiveDate, Vec<MinuteRecord>>,
+    location: &SensorLocation,
+    scalers: Option<&FittedScalers>,
+    levels: &BaselineLevels,
+    config: &Config,
+) -> (HashMap<NaiveDate, Vec<MinuteRecord>>, Vec<VentilationEvent>) {
+    let detection = match &config.ventilation {
+        Some(d) => d,
+        None => return (days, vec![]),
+    };
+    let mut events = Vec::new();
+
+    for (date, records) in days.iter_mut() {
+        let co2 = raw_series(records, "co2", location, scalers, levels);
+        let temperature = raw_series(records, "temperature", location, scalers, levels);
+        let outside = raw_series(records, "outside_temperature", location, scalers, levels);
+        let times: Vec<NaiveDateTime> = records.iter().map(|(ndt, ..)| *ndt).collect();
+        let offsets = minute_offsets(records);
+
+        let dropping: Vec<bool> = (0..records.len())
+            .map(|i| {
+                // last reading at least `slope_minutes` earlier
+                let j = match offsets[i].checked_sub(detection.slope_minutes) {
+                    Some(start) => offsets.partition_point(|offset| *offset <= start) - 1,
+                    None => return false,
+                };
+                let co2_rate = (co2[j] - co2[i]) / (offsets[i] - offsets[j]) as f32;
+                let colder_outside = temperature[i] - outside[i] >= detection.min_temperature_difference;
+                co2_rate >= detection.co2_drop && (!colder_outside || temperature[j] - temperature[i] >= detection.temperature_drop)
+            })
+            .collect();
+
+        // first minute of the current drop
+        let mut start = None;
+        for (i, record) in records.iter_mut().enumerate() {
+            start = match dropping[i] {
+                true => start.or(Some(i)),
+                false => None,
+            };
+            let minutes = start.map_or(0, |start| offsets[i] - offsets[start] + 1);
+            record.4.push(if minutes >= detection.min_minutes { 1. } else { 0. });
+
+            let run_ends = dropping.get(i + 1) != Some(&true);
+            if let (true, Some(start)) = (run_ends && minutes >= detection.min_minutes, start) {
+                events.push(VentilationEvent {
+                    location: location.clone(),
+                    date: *date,
+                    start: times[start],
+                    end: times[i],
+                    minutes,
+                    co2_start: co2[start],
+                    co2_end: co2[i],
+                    temperature_start: temperature[start],
+                    temperature_end: temperature[i],
+                    outside_temperature: outside[i],
+                });
+            }
+        }
+    }
+    (days, events)
+}
+
+#[derive(Debug, Serialize)]
+struct VentilationDay<'a> {
+    location: &'a SensorLocation,
+    date: NaiveDate,
+    events: usize,
+    minutes: usize,
+}
+
+/// Writes every event to `ventilation_events.csv` and the number of events
+/// and ventilated minutes per room and day to `ventilation_days.csv`.
+pub fn write_events(events: &mut [VentilationEvent], out_dir: &Path) -> Result<(), Box<dyn Error>> {
+    events.sort_by(|a, b| (&a.location, a.start).cmp(&(&b.location, b.start)));
+    let mut writer = csv::Writer::from_writer(File::create(out_dir.join("ventilation_events.csv"))?);
+    for event in events.iter() {
+        writer.serialize(event)?;
+    }
+    writer.flush()?;
+
+    let mut per_day: BTreeMap<(&SensorLocation, NaiveDate), (usize, usize)> = BTreeMap::new();
+    for event in events.iter() {
+        let day = per_day.entry((&event.location, event.date)).or_default();
+        *day = (day.0 + 1, day.1 + event.minutes);
+    }
+    let mut writer = csv::Writer::from_writer(File::create(out_dir.join("ventilation_days.csv"))?);
+    for ((location, date), (events, minutes)) in per_day.iter() {
+        writer.serialize(VentilationDay { location, date: *date, events: *events, minutes: *minutes })?;
+    }
+    writer.flush()?;
+
+    println!("Ventilation events: {} on {} room days", events.len(), per_day.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::VentilationDetection,
+        testing::{record, time},
+    };
+
+    fn detect(records: Vec<MinuteRecord>) -> (Vec<f32>, Vec<VentilationEvent>) {
+        let config = Config { ventilation: Some(VentilationDetection::default()), ..Config::default() };
+        let days = HashMap::from([(records[0].0.date(), records)]);
+        let (days, events) = detect_events(days, &SensorLocation::U11, None, &BaselineLevels::new(), &config);
+        (days.into_values().flatten().map(|(.., derived)| derived[0]).collect(), events)
+    }
+
+    fn at(minute: usize) -> String {
+        format!("2023-01-09 09:{:02}", minute)
+    }
+
+    #[test]
+    fn window_opening_is_flagged() {
+        // CO2 falls 40 ppm and the room 0.2 °C a minute from 09:10 to 09:17
+        let records = (0..25)
+            .map(|m| {
+                let falling = m.clamp(9, 17) as f32 - 9.;
+                let readings = [("co2", 1200. - 40. * falling), ("temperature", 22. - 0.2 * falling), ("outside_temperature", 5.)];
+                record(&at(m), &readings, 20)
+            })
+            .collect();
+        let (flags, events) = detect(records);
+
+        // the 5 minute drop reaches 100 ppm at 09:12 and is flagged after 2 minutes
+        let flagged: Vec<usize> = (0..flags.len()).filter(|m| flags[*m] == 1.).collect();
+        assert_eq!(flagged, (13..=19).collect::<Vec<_>>());
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].start, time(&at(12)));
+        assert_eq!(events[0].end, time(&at(19)));
+        assert_eq!(events[0].minutes, 8);
+    }
+
+    #[test]
+    fn no_cooling_no_event_when_cold_outside() {
+        let records = (0..25)
+            .map(|m| {
+                let falling = m.clamp(9, 17) as f32 - 9.;
+                record(&at(m), &[("co2", 1200. - 40. * falling), ("temperature", 22.), ("outside_temperature", 5.)], 20)
+            })
+            .collect();
+        let (flags, events) = detect(records);
+        assert!(flags.iter().all(|flag| *flag == 0.));
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn gaps_are_measured_in_minutes() {
+        // 10 ppm a minute is a room emptying, also over the 09:10 to 09:14 gap
+        let records = (0..30)
+            .filter(|m| !(10..15).contains(m))
+            .map(|m| record(&at(m), &[("co2", 1200. - 10. * m as f32), ("temperature", 22.), ("outside_temperature", 20.)], 20))
+            .collect();
+        let (flags, events) = detect(records);
+        assert!(flags.iter().all(|flag| *flag == 0.));
+        assert!(events.is_empty());
+    }
+}